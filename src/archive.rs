@@ -0,0 +1,127 @@
+//! Scanning `.zip`, `.tar` and `.tar.gz` archives as if they were directories.
+//!
+//! The archive's entry list is read once into the same nested listing the `--cache` uses, and
+//! the scan walks that instead of the filesystem. Directories an archive does not list
+//! explicitly (`a/` for an entry `a/b.txt`) are filled in. Entries are never extracted.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path};
+
+use flate2::read::GzDecoder;
+use log::debug;
+use serde::Serialize;
+
+use crate::cache::{CachedDir, CachedEntry};
+use crate::EntryKind;
+
+/// A supported archive format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    /// A gzip-compressed tarball (`.tar.gz` or `.tgz`).
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Guesses the format from the file name's extension, ignoring case.
+    pub fn from_path(path: &Path) -> Option<ArchiveFormat> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads the entry list of the archive at `path` as a listing tree.
+pub(crate) fn load(path: &Path, format: ArchiveFormat) -> io::Result<CachedDir> {
+    let file = BufReader::new(File::open(path)?);
+    let mut root = CachedDir::default();
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(io::Error::other)?;
+            for index in 0..archive.len() {
+                let entry = archive.by_index_raw(index).map_err(io::Error::other)?;
+                let kind = if entry.is_dir() {
+                    EntryKind::Dir
+                } else if entry.is_symlink() {
+                    EntryKind::Symlink
+                } else {
+                    EntryKind::File
+                };
+                // `enclosed_name` drops names that would escape the archive (`../x`, `/etc`).
+                if let Some(name) = entry.enclosed_name() {
+                    insert(&mut root, &name, kind);
+                }
+            }
+        }
+        ArchiveFormat::Tar => load_tar(file, &mut root)?,
+        ArchiveFormat::TarGz => load_tar(GzDecoder::new(file), &mut root)?,
+    }
+    debug!("read the entries of {} as {:?}", path.display(), format);
+    Ok(root)
+}
+
+fn load_tar(reader: impl Read, root: &mut CachedDir) -> io::Result<()> {
+    for entry in tar::Archive::new(reader).entries()? {
+        let entry = entry?;
+        let kind = match entry.header().entry_type() {
+            tar::EntryType::Directory => EntryKind::Dir,
+            tar::EntryType::Symlink => EntryKind::Symlink,
+            tar::EntryType::Regular | tar::EntryType::Link | tar::EntryType::Continuous => EntryKind::File,
+            // Devices, fifos and metadata records have no place in the tree.
+            _ => continue,
+        };
+        insert(root, &entry.path()?, kind);
+    }
+    Ok(())
+}
+
+/// Adds the entry at `path` (relative to the archive root) to `root`, creating its parent
+/// directories as needed. Entries listed twice are kept once.
+fn insert(root: &mut CachedDir, path: &Path, kind: EntryKind) {
+    let names: Vec<String> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    let Some((last, parents)) = names.split_last() else {
+        return;
+    };
+    let mut dir = root;
+    for name in parents {
+        let parent = child(dir, name, EntryKind::Dir);
+        parent.kind = EntryKind::Dir;
+        dir = parent.dir.get_or_insert_with(CachedDir::default);
+    }
+    let entry = child(dir, last, kind);
+    if kind == EntryKind::Dir {
+        entry.dir.get_or_insert_with(CachedDir::default);
+    }
+}
+
+/// Returns the entry `name` of `dir`, adding it with `kind` if it is missing.
+fn child<'d>(dir: &'d mut CachedDir, name: &str, kind: EntryKind) -> &'d mut CachedEntry {
+    let index = match dir.entries.iter().position(|entry| entry.name == name) {
+        Some(index) => index,
+        None => {
+            dir.entries.push(CachedEntry {
+                name: name.to_string(),
+                kind,
+                dir: None,
+            });
+            dir.entries.len() - 1
+        }
+    };
+    &mut dir.entries[index]
+}
@@ -0,0 +1,147 @@
+//! `--cache`: an on-disk copy of the directory structure, so repeated searches skip the walk.
+//!
+//! The cache holds listings (entry names and kinds), never match results, so any search can
+//! reuse it. Each scanned directory is still `stat`ed, and one whose modification time changed
+//! since it was cached is listed again; unchanged ones are served from the cache.
+//!
+//! There is one JSON file per root, named after a hash of its canonical path, in
+//! `$XDG_CACHE_HOME/xtree`, `~/.cache/xtree` or `%LOCALAPPDATA%\xtree`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+
+use crate::EntryKind;
+
+/// Bumped whenever the file layout changes; files of other versions are ignored.
+const VERSION: u32 = 1;
+
+/// A cache file: the listings below `root`.
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    root: PathBuf,
+    tree: CachedDir,
+}
+
+/// The listing of one directory as of its modification time `mtime`. Archives are read into the
+/// same structure, with no time.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct CachedDir {
+    /// Nanoseconds since the Unix epoch.
+    mtime: u128,
+    pub(crate) entries: Vec<CachedEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CachedEntry {
+    pub(crate) name: String,
+    pub(crate) kind: EntryKind,
+    /// The subdirectory's own listing, once a scan has descended into it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dir: Option<CachedDir>,
+}
+
+/// Returns the directory holding cache files, if the environment names one.
+pub(crate) fn cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    base.map(|base| base.join("xtree"))
+}
+
+/// Returns the cache file for `root` and the canonical root it is keyed by.
+fn cache_file(root: &Path) -> Option<(PathBuf, PathBuf)> {
+    let root = fs::canonicalize(root).ok()?;
+    let mut hasher = DefaultHasher::new();
+    root.hash(&mut hasher);
+    Some((cache_dir()?.join(format!("{:016x}.json", hasher.finish())), root))
+}
+
+/// Loads the cached listings below `root`. A missing, unreadable or outdated file yields `None`.
+pub(crate) fn load(root: &Path) -> Option<CachedDir> {
+    let (file, root) = cache_file(root)?;
+    let Some(cached) = fs::read(&file).ok().and_then(|bytes| serde_json::from_slice::<CacheFile>(&bytes).ok()) else {
+        debug!("no usable cache at {}", file.display());
+        return None;
+    };
+    (cached.version == VERSION && cached.root == root).then_some(cached.tree)
+}
+
+/// Saves the listings below `root`, replacing any earlier cache file for it.
+pub(crate) fn store(root: &Path, tree: CachedDir) -> io::Result<()> {
+    let (file, root) = cache_file(root).ok_or_else(|| io::Error::other("no cache directory"))?;
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let cached = CacheFile {
+        version: VERSION,
+        root,
+        tree,
+    };
+    // Write then rename, so a concurrent search never reads half a file.
+    let partial = file.with_extension("json.tmp");
+    fs::write(&partial, serde_json::to_vec(&cached)?)?;
+    fs::rename(partial, file)
+}
+
+/// Removes every cache file.
+pub fn clear_cache() -> io::Result<()> {
+    match cache_dir() {
+        Some(dir) => match fs::remove_dir_all(dir) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+        None => Ok(()),
+    }
+}
+
+/// Returns the listing of `path` from `slot`, re-reading the directory when it is not cached yet
+/// or was modified since. Subdirectories that are still present keep their cached listings, to
+/// be validated in turn when the scan reaches them.
+pub(crate) fn refresh<'a>(slot: &'a mut Option<CachedDir>, path: &Path) -> Option<&'a mut CachedDir> {
+    let mtime = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos();
+    if slot.as_ref().is_some_and(|dir| dir.mtime == mtime) {
+        trace!("listing {} from the cache", path.display());
+        return slot.as_mut();
+    }
+    debug!("listing {}: not cached or modified since", path.display());
+    let mut old: HashMap<String, CachedDir> = slot
+        .take()
+        .into_iter()
+        .flat_map(|dir| dir.entries)
+        .filter(|entry| entry.kind == EntryKind::Dir)
+        .filter_map(|entry| Some((entry.name, entry.dir?)))
+        .collect();
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path).ok()?.flatten() {
+        let Some(kind) = entry.file_type().ok().and_then(EntryKind::of) else {
+            continue;
+        };
+        // A name that is not valid UTF-8 could not be joined back into its path; leave the
+        // directory uncached so it is always listed live.
+        let name = entry.file_name().into_string().ok()?;
+        let dir = if kind == EntryKind::Dir { old.remove(&name) } else { None };
+        entries.push(CachedEntry { name, kind, dir });
+    }
+    *slot = Some(CachedDir { mtime, entries });
+    slot.as_mut()
+}
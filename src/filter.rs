@@ -0,0 +1,384 @@
+//! The `--filter` expression language.
+//!
+//! An expression combines comparisons on directory attributes with `&&`, `||`, `!` and
+//! parentheses, e.g. `name~foo && depth<4 && mtime<7d`:
+//!
+//! - `name` supports `~` (contains), `!~`, `=` and `!=`, compared case-insensitively.
+//! - `depth` is the level below the scan root (the root is 0) and supports `=`, `!=`, `<`,
+//!   `<=`, `>` and `>=`.
+//! - `mtime` is the time since the directory was last modified and takes a duration with a unit
+//!   of `s`, `m`, `h`, `d` or `w`; `mtime<7d` means "modified within the last week".
+//!
+//! Values may be quoted with `"` or `'` to include spaces or operator characters.
+
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use serde::{Serialize, Serializer};
+
+use crate::FileSource;
+
+/// A parsed `--filter` expression.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+    source: String,
+}
+
+/// Serializes as the source expression.
+impl Serialize for Filter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+/// An invalid `--filter` expression, with the byte offset where parsing failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for FilterError {}
+
+/// What a filter is evaluated against.
+pub(crate) struct Candidate<'a> {
+    pub name_lower: &'a str,
+    pub path: &'a Path,
+    pub depth: usize,
+    pub files: &'a dyn FileSource,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Name(NameOp, String),
+    Depth(Cmp, usize),
+    Mtime(Cmp, Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NameOp {
+    Contains,
+    NotContains,
+    Equals,
+    NotEquals,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    fn holds<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Cmp::Eq => left == right,
+            Cmp::Ne => left != right,
+            Cmp::Lt => left < right,
+            Cmp::Le => left <= right,
+            Cmp::Gt => left > right,
+            Cmp::Ge => left >= right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Op(&'static str),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+/// Comparison operators, longest first so that `<=` is not read as `<`.
+const OPS: [&str; 9] = ["!~", "!=", "<=", ">=", "==", "~", "=", "<", ">"];
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        let pos = input.len() - rest.len();
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let (token, len) = if rest.starts_with("&&") {
+            (Token::And, 2)
+        } else if rest.starts_with("||") {
+            (Token::Or, 2)
+        } else if c == '(' {
+            (Token::Open, 1)
+        } else if c == ')' {
+            (Token::Close, 1)
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            (Token::Op(op), op.len())
+        } else if c == '!' {
+            (Token::Not, 1)
+        } else if c == '"' || c == '\'' {
+            let end = rest[1..].find(c).ok_or_else(|| FilterError {
+                message: "unterminated quoted value".to_string(),
+                position: pos,
+            })?;
+            (Token::Word(rest[1..=end].to_string()), end + 2)
+        } else {
+            let len = rest
+                .find(|c: char| c.is_whitespace() || "()&|!~=<>\"'".contains(c))
+                .unwrap_or(rest.len());
+            (Token::Word(rest[..len].to_string()), len)
+        };
+        tokens.push((pos, token));
+        rest = &rest[len..];
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(pos, _)| *pos)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, FilterError> {
+        Err(FilterError {
+            message: message.into(),
+            position: self.position(),
+        })
+    }
+
+    fn bump(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.bump();
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.bump();
+            left = Expr::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, FilterError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.bump();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.bump();
+                let inner = self.or()?;
+                if self.bump() != Some(Token::Close) {
+                    self.next -= 1;
+                    return self.error("expected `)`");
+                }
+                Ok(inner)
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, FilterError> {
+        let field = match self.peek() {
+            Some(Token::Word(word)) => word.clone(),
+            _ => return self.error("expected `name`, `depth` or `mtime`"),
+        };
+        if !matches!(field.as_str(), "name" | "depth" | "mtime") {
+            return self.error(format!("unknown field `{}`", field));
+        }
+        self.bump();
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => return self.error(format!("expected a comparison after `{}`", field)),
+        };
+        self.bump();
+        let value = match self.peek() {
+            Some(Token::Word(value)) => value.clone(),
+            _ => return self.error(format!("expected a value after `{}{}`", field, op)),
+        };
+
+        let expr = match field.as_str() {
+            "name" => {
+                let name_op = match op {
+                    "~" => NameOp::Contains,
+                    "!~" => NameOp::NotContains,
+                    "=" | "==" => NameOp::Equals,
+                    "!=" => NameOp::NotEquals,
+                    _ => return self.error(format!("`name` does not support `{}`", op)),
+                };
+                Expr::Name(name_op, value.to_lowercase())
+            }
+            "depth" => {
+                let cmp = self.cmp(op, "depth")?;
+                match value.parse() {
+                    Ok(depth) => Expr::Depth(cmp, depth),
+                    Err(_) => return self.error(format!("invalid depth `{}`", value)),
+                }
+            }
+            _ => {
+                let cmp = self.cmp(op, "mtime")?;
+                match parse_duration(&value) {
+                    Some(age) => Expr::Mtime(cmp, age),
+                    None => return self.error(format!("invalid duration `{}` (use e.g. 30m, 12h, 7d)", value)),
+                }
+            }
+        };
+        self.bump();
+        Ok(expr)
+    }
+
+    fn cmp(&self, op: &str, field: &str) -> Result<Cmp, FilterError> {
+        match op {
+            "=" | "==" => Ok(Cmp::Eq),
+            "!=" => Ok(Cmp::Ne),
+            "<" => Ok(Cmp::Lt),
+            "<=" => Ok(Cmp::Le),
+            ">" => Ok(Cmp::Gt),
+            ">=" => Ok(Cmp::Ge),
+            _ => self.error(format!("`{}` does not support `{}`", field, op)),
+        }
+    }
+}
+
+/// Parses durations like `90s`, `30m`, `12h`, `7d` or `2w`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let amount: u64 = value[..split].parse().ok()?;
+    let unit = match &value[split..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(unit)?))
+}
+
+impl Filter {
+    /// Parses a filter expression, reporting the first syntax error.
+    pub fn parse(input: &str) -> Result<Filter, FilterError> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            next: 0,
+            end: input.len(),
+        };
+        if parser.peek().is_none() {
+            return parser.error("empty expression");
+        }
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return parser.error("unexpected input");
+        }
+        Ok(Filter {
+            expr,
+            source: input.to_string(),
+        })
+    }
+
+    pub(crate) fn eval(&self, candidate: &Candidate) -> bool {
+        eval(&self.expr, candidate)
+    }
+}
+
+fn eval(expr: &Expr, candidate: &Candidate) -> bool {
+    match expr {
+        Expr::And(left, right) => eval(left, candidate) && eval(right, candidate),
+        Expr::Or(left, right) => eval(left, candidate) || eval(right, candidate),
+        Expr::Not(inner) => !eval(inner, candidate),
+        Expr::Name(op, value) => match op {
+            NameOp::Contains => candidate.name_lower.contains(value.as_str()),
+            NameOp::NotContains => !candidate.name_lower.contains(value.as_str()),
+            NameOp::Equals => candidate.name_lower == value,
+            NameOp::NotEquals => candidate.name_lower != value,
+        },
+        Expr::Depth(cmp, depth) => cmp.holds(candidate.depth, *depth),
+        Expr::Mtime(cmp, age) => candidate
+            .files
+            .metadata(candidate.path)
+            .ok()
+            .and_then(|metadata| metadata.modified)
+            .map(|modified| SystemTime::now().duration_since(modified).unwrap_or_default())
+            .is_some_and(|actual| cmp.holds(actual, *age)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFiles;
+
+    /// Evaluates `input` for a directory modified an hour ago.
+    fn eval_at(input: &str, name_lower: &str, depth: usize) -> bool {
+        let filter = Filter::parse(input).expect("valid filter");
+        let mut files = MemoryFiles::new();
+        files.add_dir("dir").set_modified("dir", SystemTime::now() - Duration::from_secs(3600));
+        filter.eval(&Candidate {
+            name_lower,
+            path: Path::new("dir"),
+            depth,
+            files: &files,
+        })
+    }
+
+    #[test]
+    fn combines_comparisons_with_precedence() {
+        assert!(eval_at("name~foo && depth<4", "foobar", 2));
+        assert!(!eval_at("name~foo && depth<4", "foobar", 4));
+        assert!(eval_at("name=x || name~foo && depth>9", "x", 1));
+        assert!(!eval_at("!(name~foo || name~bar)", "bar", 1));
+        assert!(eval_at("name!~'a b'", "ab", 1));
+    }
+
+    #[test]
+    fn recent_directories_satisfy_mtime() {
+        assert!(eval_at("mtime<7d", "", 0));
+        assert!(!eval_at("mtime>1w", "", 0));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for input in ["", "name", "size>3", "depth~2", "mtime<7", "(name~a", "name~a b"] {
+            assert!(Filter::parse(input).is_err(), "{:?} should not parse", input);
+        }
+        assert_eq!(Filter::parse("depth<x").unwrap_err().position, 6);
+    }
+}
@@ -0,0 +1,189 @@
+//! `.xtreeignore` files: gitignore-style patterns that hide entries from a scan.
+
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::FileSource;
+
+/// Name of the per-directory ignore file.
+pub(crate) const IGNORE_FILE: &str = ".xtreeignore";
+
+/// The patterns of one `.xtreeignore` file, relative to the directory holding it.
+#[derive(Debug)]
+pub(crate) struct IgnoreFile {
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    glob: Vec<char>,
+    /// `!pattern` re-includes an entry ignored by an earlier pattern.
+    negated: bool,
+    /// Patterns containing a slash match the path relative to the file; others match any basename.
+    anchored: bool,
+    /// `pattern/` matches directories only.
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let glob = line.strip_prefix('/').unwrap_or(line);
+        if glob.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            glob: glob.chars().collect(),
+            negated,
+            anchored,
+            dir_only,
+        })
+    }
+}
+
+impl IgnoreFile {
+    /// Loads `dir/.xtreeignore` from `files`, returning `None` when it is missing, unreadable or
+    /// empty.
+    pub(crate) fn load(files: &dyn FileSource, dir: &Path) -> Option<IgnoreFile> {
+        let text = files.read_to_string(&dir.join(IGNORE_FILE)).ok()?;
+        let rules: Vec<Rule> = text.lines().filter_map(Rule::parse).collect();
+        if rules.is_empty() {
+            return None;
+        }
+        debug!("loaded {} pattern(s) from {}", rules.len(), dir.join(IGNORE_FILE).display());
+        Some(IgnoreFile {
+            base: dir.to_path_buf(),
+            rules,
+        })
+    }
+
+    /// Returns `Some(true)` if the last pattern matching `path` (a directory when `is_dir`)
+    /// ignores it, `Some(false)` if it re-includes it, and `None` if no pattern applies.
+    fn verdict(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let rel = path.strip_prefix(&self.base).ok()?;
+        let rel: Vec<String> = rel
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let rel_path: Vec<char> = rel.join("/").chars().collect();
+        let name: Vec<char> = rel.last()?.chars().collect();
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.dir_only)
+            .find(|rule| glob_match(&rule.glob, if rule.anchored { &rel_path } else { &name }))
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Returns true if `path`, a directory when `is_dir`, is ignored by `files`, given from the
+/// root down.
+///
+/// Deeper files override shallower ones, and within a file the last matching pattern wins.
+pub(crate) fn is_ignored<'a, I>(files: I, path: &Path, is_dir: bool) -> bool
+where
+    I: DoubleEndedIterator<Item = &'a IgnoreFile>,
+{
+    files.rev().find_map(|file| file.verdict(path, is_dir)).unwrap_or(false)
+}
+
+/// Matches `text` against a gitignore-style glob: `*` and `?` stay within one path segment,
+/// `**` spans segments, `[...]` is a character class and `\` escapes the next character.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` may also match no directories at all.
+            if let ['/', after @ ..] = rest {
+                if glob_match(after, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != '/' && glob_match(rest, tail)),
+        ['[', rest @ ..] => match (text, match_class(rest, text.first().copied())) {
+            ([_, tail @ ..], Some((true, after))) => glob_match(after, tail),
+            (_, Some((false, _))) => false,
+            // An unterminated class is a literal `[`.
+            _ => text.first() == Some(&'[') && glob_match(rest, &text[1..]),
+        },
+        ['\\', c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// Matches `c` against the character class starting right after `[`. Returns whether it
+/// matched and the pattern following the closing `]`, or `None` if the class is unterminated.
+fn match_class(class: &[char], c: Option<char>) -> Option<(bool, &[char])> {
+    let (negated, mut rest) = match class {
+        ['!' | '^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [']', after @ ..] if !first => {
+                let matched = c.is_some_and(|c| c != '/') && matched != negated;
+                return Some((matched, after));
+            }
+            [lo, '-', hi, after @ ..] if *hi != ']' => {
+                matched |= c.is_some_and(|c| (*lo..=*hi).contains(&c));
+                rest = after;
+            }
+            [item, after @ ..] => {
+                matched |= c == Some(*item);
+                rest = after;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        glob_match(&pattern, &text)
+    }
+
+    #[test]
+    fn glob_wildcards_stay_within_a_segment() {
+        assert!(matches("build*", "build-cache"));
+        assert!(matches("?uild", "build"));
+        assert!(!matches("a*c", "a/b/c"));
+        assert!(matches("a/**/c", "a/b/c"));
+        assert!(matches("a/**/c", "a/c"));
+        assert!(matches("**/c", "c"));
+    }
+
+    #[test]
+    fn glob_character_classes() {
+        assert!(matches("v[0-9]", "v7"));
+        assert!(!matches("v[!0-9]", "v7"));
+        assert!(matches("[]]x", "]x"));
+        assert!(matches("[x", "[x"));
+    }
+}
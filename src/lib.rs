@@ -0,0 +1,1999 @@
+//! Core scanning and matching for xtree.
+//!
+//! [`build_tree_dict`] builds the filtered [`Tree`] that the `xtree` binary renders, while
+//! [`matches`] yields matching directories lazily for consumers that want a stream, and [`walk`]
+//! hands every visited entry to a callback.
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+use unicode_normalization::UnicodeNormalization;
+
+mod archive;
+mod cache;
+mod filter;
+mod ignore;
+mod source;
+
+pub use archive::ArchiveFormat;
+pub use cache::clear_cache;
+pub use filter::{Filter, FilterError};
+pub use source::{EntryMetadata, FileSource, MemoryFiles, OsFiles};
+
+use cache::CachedDir;
+use filter::Candidate;
+use ignore::{is_ignored, IgnoreFile};
+
+/// ANSI colors (escape, name) cycled through for successive search terms; the first term is red.
+pub const HIGHLIGHT_COLORS: [(&str, &str); 6] = [
+    ("\x1b[91m", "red"),
+    ("\x1b[92m", "green"),
+    ("\x1b[93m", "yellow"),
+    ("\x1b[94m", "blue"),
+    ("\x1b[95m", "magenta"),
+    ("\x1b[96m", "cyan"),
+];
+
+/// Directories [`ScanOptions::smart_ignore`] collapses with `--smart-ignore`: build output,
+/// installed dependencies, virtual environments and version control data.
+pub const SMART_IGNORE_DIRS: [&str; 7] = ["target", "node_modules", ".git", "venv", "dist", "build", "__pycache__"];
+
+/// The kinds of entries that can be matched, mirroring `find -type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+}
+
+impl EntryKind {
+    /// Classifies a (not followed) file type; sockets, FIFOs and devices have no kind.
+    pub(crate) fn of(file_type: fs::FileType) -> Option<EntryKind> {
+        if file_type.is_dir() {
+            Some(EntryKind::Dir)
+        } else if file_type.is_file() {
+            Some(EntryKind::File)
+        } else if file_type.is_symlink() {
+            Some(EntryKind::Symlink)
+        } else {
+            None
+        }
+    }
+}
+
+/// A simple tree structure representing a directory and its matching subdirectories.
+///
+/// When [`ScanOptions::types`] admits files or symlinks, matching ones appear as leaf nodes.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tree {
+    /// [`node_id`] of the path relative to the root, identifying the node across runs.
+    pub id: String,
+    pub name: String,
+    pub kind: EntryKind,
+    pub matched: bool,
+    /// Number of matching directories in this subtree, including this one.
+    pub score: u32,
+    /// Permission string, recorded only when [`ScanOptions::perms`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perms: Option<String>,
+    /// Labels of the [`ScanOptions::labels`] patterns the name contains, in manifest order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// Number of immediate subdirectories, recorded for matches when
+    /// [`ScanOptions::min_children`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subdirs: Option<usize>,
+    /// Total size in bytes, recorded for matches when [`ScanOptions::larger_than`] or
+    /// [`ScanOptions::smaller_than`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Length of the name in Unicode scalar values, recorded for matches when
+    /// [`ScanOptions::name_longer_than`] or [`ScanOptions::name_shorter_than`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_len: Option<usize>,
+    /// Number of lines, recorded for matching files when [`ScanOptions::min_lines`] or
+    /// [`ScanOptions::max_lines`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<u64>,
+    /// Modification time in seconds since the Unix epoch, recorded when [`ScanOptions::mtimes`]
+    /// is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    /// Number of matches below this node whose branches [`Tree::flatten`] removed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapsed: Option<u32>,
+    /// Number of immediate entries of a directory [`ScanOptions::smart_ignore`] did not descend
+    /// into; what they hold is not counted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignored: Option<u64>,
+    /// Number of trees this node was found in, recorded by [`Tree::merge`] when more than one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roots: Option<u32>,
+    pub children: Vec<Tree>,
+}
+
+impl Tree {
+    /// Counts the matching directories kept in this tree, including this one. Unlike `score`,
+    /// this ignores matches pruned by [`ScanOptions::min_score`].
+    pub fn count_matches(&self) -> u32 {
+        u32::from(self.matched)
+            + self.collapsed.unwrap_or(0)
+            + self.children.iter().map(Tree::count_matches).sum::<u32>()
+    }
+
+    /// Orders the children at every level by [`Tree::mtime`], oldest first or with
+    /// `newest_first` newest first. Entries without a time go last; ties keep their order.
+    pub fn sort_by_mtime(&mut self, newest_first: bool) {
+        if newest_first {
+            self.children.sort_by_key(|child| (child.mtime.is_none(), Reverse(child.mtime)));
+        } else {
+            self.children.sort_by_key(|child| (child.mtime.is_none(), child.mtime));
+        }
+        for child in &mut self.children {
+            child.sort_by_mtime(newest_first);
+        }
+    }
+
+    /// Reverses the order of the children at every level.
+    pub fn reverse(&mut self) {
+        self.children.reverse();
+        for child in &mut self.children {
+            child.reverse();
+        }
+    }
+
+    /// Removes everything `depth` levels below this node, recording on each node at that depth
+    /// how many matches its removed branches held, so totals stay the same.
+    pub fn flatten(&mut self, depth: usize) {
+        if depth == 0 {
+            let below: u32 = self.children.drain(..).map(|child| child.count_matches()).sum();
+            if below > 0 {
+                self.collapsed = Some(self.collapsed.unwrap_or(0) + below);
+            }
+        } else {
+            for child in &mut self.children {
+                child.flatten(depth - 1);
+            }
+        }
+    }
+
+    /// Reduces the tree to the sparsest one that still shows where every match lives: branches
+    /// without matches are dropped, and a directory that did not match and holds a single branch
+    /// is folded into it, the branch's name becoming the joined path (`a/b/test`).
+    pub fn summarize(&mut self) {
+        self.children.retain(|child| child.score > 0 || child.collapsed.is_some());
+        for child in &mut self.children {
+            child.summarize();
+            while !child.matched && child.collapsed.is_none() && child.children.len() == 1 {
+                let only = child.children.remove(0);
+                let name = format!("{}/{}", child.name, only.name);
+                *child = Tree { name, ..only };
+            }
+        }
+    }
+
+    /// Overlays `other`, the tree of another root, onto this one: children with the same name
+    /// and kind are merged in turn and the others added, scores add up and a node matches if it
+    /// matched in either. The details of matches found in both are kept from this tree.
+    pub fn merge(&mut self, other: Tree) {
+        self.roots = Some(self.roots.unwrap_or(1) + other.roots.unwrap_or(1));
+        self.matched |= other.matched;
+        self.score += other.score;
+        self.collapsed = match (self.collapsed, other.collapsed) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        for label in other.labels {
+            if !self.labels.contains(&label) {
+                self.labels.push(label);
+            }
+        }
+        for child in other.children {
+            match self.children.iter_mut().find(|mine| mine.name == child.name && mine.kind == child.kind) {
+                Some(mine) => mine.merge(child),
+                None => self.children.push(child),
+            }
+        }
+    }
+}
+
+/// Settings that control how directories are scanned and matched.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanOptions {
+    pub max_depth: usize,
+    /// Lower-cased search terms (as typed when [`ScanOptions::case_sensitive`]); a directory
+    /// matches if its name contains any of them.
+    pub terms_lower: Vec<String>,
+    /// Terms, folded like `terms_lower`, that rule a match out when the name contains any of them
+    /// (`--not-term`); with no other terms, everything else matches.
+    pub excluded: Vec<String>,
+    /// Require every term in `terms_lower` instead of any one (`--match all`).
+    pub match_all: bool,
+    /// Extra condition every match must satisfy; with no terms, it alone decides what matches.
+    pub filter: Option<Filter>,
+    /// Kinds of entries that can match (`--type`); directories are traversed regardless.
+    pub types: Vec<EntryKind>,
+    /// Compare names and terms in Unicode NFC form; see [`ScanOptions::set_normalize`].
+    normalize: bool,
+    /// Compare names and terms without diacritics; see [`ScanOptions::set_ignore_accents`].
+    ignore_accents: bool,
+    /// Compare names and terms without folding case; see [`ScanOptions::new_case_sensitive`].
+    case_sensitive: bool,
+    /// Test and count the root directory's basename like any other directory.
+    pub count_root: bool,
+    /// Read each directory's metadata to record its permission bits.
+    pub perms: bool,
+    /// Device id of the root; when set, directories on other devices are not descended into.
+    pub root_dev: Option<u64>,
+    /// Keep directories without matches as context instead of pruning them.
+    pub show_empty: bool,
+    /// Minimum subtree score a branch needs to be shown (see [`build_tree_dict`]).
+    pub min_score: u32,
+    /// Honor `.xtreeignore` files found in the root and any scanned directory.
+    pub xtreeignore: bool,
+    /// `(pattern, label)` pairs from `--match-manifest`, with lower-cased patterns: matches whose
+    /// names contain a pattern are tagged with its label. The patterns must also be search terms
+    /// to make anything match.
+    pub labels: Vec<(String, String)>,
+    /// Entry names scanned even when an ignore pattern excludes them (`--include .git`).
+    pub include: Vec<String>,
+    /// Names of directories that are not descended into (`--smart-ignore`, see
+    /// [`SMART_IGNORE_DIRS`]); tree scans still show them next to the other entries, collapsed,
+    /// with [`Tree::ignored`]. Names in `include` are scanned as usual.
+    pub smart_ignore: Vec<String>,
+    /// Reuse and update the on-disk copy of the directory structure (see `--cache`); only
+    /// [`build_tree_dict`] uses it.
+    pub cache: bool,
+    /// Paths, relative to the root, of changed files and every directory containing one; when
+    /// set, nothing else can match (`--since-commit`). The root itself is the empty path.
+    pub changed: Option<BTreeSet<PathBuf>>,
+    /// Only match directories with at least this many immediate subdirectories
+    /// (`--min-children`); with no terms, it alone decides what matches.
+    pub min_children: Option<usize>,
+    /// Only match directories with nothing in them at all (`--empty`), whatever is shown; with no
+    /// terms, it alone decides what matches.
+    pub empty: bool,
+    /// Match a directory when, and only when, one of its own files or symlinks matches
+    /// (`--only-dirs-with-files`), whatever its name; needs [`ScanOptions::types`] to allow them.
+    /// Only [`build_tree_dict`] scans honour it.
+    pub only_dirs_with_files: bool,
+    /// Read the root as an archive of this format (`--archive`); without it, files named
+    /// `*.zip`, `*.tar`, `*.tar.gz` or `*.tgz` are (see [`ScanOptions::archive_format`]).
+    pub archive: Option<ArchiveFormat>,
+    /// Only match entries whose total size (see [`disk_usage`]) is above this many bytes
+    /// (`--larger-than`); with no terms, the size bounds alone decide what matches.
+    pub larger_than: Option<u64>,
+    /// Only match entries whose total size is below this many bytes (`--smaller-than`).
+    pub smaller_than: Option<u64>,
+    /// Only match entries whose name is longer than this many characters (Unicode scalar values,
+    /// not bytes; `--name-longer-than`); with no terms, the length bounds alone decide what
+    /// matches.
+    pub name_longer_than: Option<usize>,
+    /// Only match entries whose name is shorter than this many characters (`--name-shorter-than`).
+    pub name_shorter_than: Option<usize>,
+    /// Only match regular files of at least this many lines (`--min-lines`), counted by reading
+    /// them; binary files (those with a NUL byte) never match. With no terms, the line bounds
+    /// alone decide what matches.
+    pub min_lines: Option<u64>,
+    /// Only match regular files of at most this many lines (`--max-lines`).
+    pub max_lines: Option<u64>,
+    /// Match every entry (`xtree tree`), as long as it satisfies the other conditions.
+    pub all: bool,
+    /// Only match entries carrying this extended attribute, with exactly this value if one is
+    /// given (`--xattr NAME[=VALUE]`); with no terms, it alone decides what matches. Ignored where
+    /// [`XATTR_SUPPORTED`] is false.
+    pub xattr: Option<(String, Option<String>)>,
+    /// Only match entries owned by this user id (`--owner`); with no terms, it alone decides what
+    /// matches. Ignored on platforms without Unix ownership.
+    pub owner: Option<u32>,
+    /// Only match entries owned by this group id (`--group`), like `owner`.
+    pub group: Option<u32>,
+    /// Fail on the first directory that cannot be read (`--strict`) instead of scanning it as if
+    /// empty; see [`ScanError`].
+    pub strict: bool,
+    /// Stop listing directories once this many have been (`--scan-budget`), leaving the rest of
+    /// the tree unscanned; see [`ScanReport::over_budget`]. Only [`build_tree_dict`] scans honour it.
+    pub scan_budget: Option<usize>,
+    /// Let a term (or a segment of a structural one) match a part of the name within this many
+    /// character edits of it (`--max-distance`).
+    pub max_distance: Option<usize>,
+    /// Where listings, entry kinds, sizes and modification times are read from: the real
+    /// filesystem ([`OsFiles`]) unless replaced, e.g. by a [`MemoryFiles`] tree.
+    #[serde(skip)]
+    pub files: Arc<dyn FileSource>,
+    /// Record the modification time of every entry kept in the tree, for [`Tree::sort_by_mtime`]
+    /// (`--sort mtime`).
+    pub mtimes: bool,
+}
+
+impl ScanOptions {
+    /// Creates options for the given search terms with the CLI defaults (depth 3, root counted,
+    /// Unicode normalization on macOS only). Terms are lower-cased and empty terms are dropped.
+    pub fn new<I, S>(terms: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut opts = ScanOptions {
+            max_depth: 3,
+            terms_lower: terms
+                .into_iter()
+                .filter(|term| !term.as_ref().is_empty())
+                .map(|term| term.as_ref().to_lowercase())
+                .collect(),
+            excluded: Vec::new(),
+            match_all: false,
+            filter: None,
+            types: vec![EntryKind::Dir],
+            normalize: false,
+            ignore_accents: false,
+            case_sensitive: false,
+            count_root: true,
+            perms: false,
+            root_dev: None,
+            show_empty: false,
+            min_score: 1,
+            xtreeignore: true,
+            labels: Vec::new(),
+            include: Vec::new(),
+            smart_ignore: Vec::new(),
+            cache: false,
+            changed: None,
+            min_children: None,
+            empty: false,
+            only_dirs_with_files: false,
+            archive: None,
+            larger_than: None,
+            smaller_than: None,
+            name_longer_than: None,
+            name_shorter_than: None,
+            min_lines: None,
+            max_lines: None,
+            all: false,
+            xattr: None,
+            owner: None,
+            group: None,
+            strict: false,
+            scan_budget: None,
+            max_distance: None,
+            files: Arc::new(OsFiles),
+            mtimes: false,
+        };
+        opts.set_normalize(cfg!(target_os = "macos"));
+        opts
+    }
+
+    /// Like [`ScanOptions::new`], but the terms keep their case and only match names containing
+    /// them exactly as typed (`Src` matches `MySrc`, not `src`). `--filter` and
+    /// `--match-manifest` patterns still ignore case.
+    pub fn new_case_sensitive<I, S>(terms: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut opts = ScanOptions::new(std::iter::empty::<&str>());
+        opts.case_sensitive = true;
+        opts.terms_lower = terms
+            .into_iter()
+            .filter(|term| !term.as_ref().is_empty())
+            .map(|term| term.as_ref().to_string())
+            .collect();
+        opts.set_normalize(opts.normalize);
+        opts
+    }
+
+    /// Whether terms are matched with their case (see [`ScanOptions::new_case_sensitive`]).
+    pub fn case_sensitive(&self) -> bool {
+        self.case_sensitive
+    }
+
+    /// Enables or disables Unicode normalization. When enabled, names and terms are both brought
+    /// to NFC before comparing, so a term typed in composed form (`café`) matches a name stored
+    /// decomposed (`cafe` + U+0301), as macOS filesystems do. The current terms are normalized here,
+    /// so call this after setting `terms_lower` (and `labels`); disabling it does not undo that.
+    pub fn set_normalize(&mut self, normalize: bool) {
+        self.normalize = normalize;
+        if normalize {
+            let patterns = self.labels.iter_mut().map(|(pattern, _)| pattern);
+            for term in self.terms_lower.iter_mut().chain(&mut self.excluded).chain(patterns) {
+                *term = term.nfc().collect();
+            }
+        }
+    }
+
+    /// Whether names and terms are compared in NFC form.
+    pub fn normalize(&self) -> bool {
+        self.normalize
+    }
+
+    /// Whether `name` is whitelisted by `include`, overriding any ignore pattern.
+    fn includes(&self, name: &OsStr) -> bool {
+        self.include.iter().any(|included| OsStr::new(included) == name)
+    }
+
+    /// Whether a directory named `name` is collapsed by `smart_ignore`.
+    fn smart_ignored(&self, name: &OsStr) -> bool {
+        self.smart_ignore.iter().any(|ignored| OsStr::new(ignored) == name) && !self.includes(name)
+    }
+
+    /// Enables or disables accent-insensitive matching, so `cafe` matches `café` and vice versa.
+    /// Like [`ScanOptions::set_normalize`], this folds the current terms, so call it after setting
+    /// `terms_lower`.
+    pub fn set_ignore_accents(&mut self, ignore_accents: bool) {
+        self.ignore_accents = ignore_accents;
+        if ignore_accents {
+            let patterns = self.labels.iter_mut().map(|(pattern, _)| pattern);
+            for term in self.terms_lower.iter_mut().chain(&mut self.excluded).chain(patterns) {
+                *term = fold_with_offsets(term, true, !self.case_sensitive).0;
+            }
+        }
+    }
+
+    /// Whether diacritics are stripped from names and terms before comparing.
+    pub fn ignore_accents(&self) -> bool {
+        self.ignore_accents
+    }
+
+    /// Returns, for every search term that the entry at `path` (`depth` levels below the root)
+    /// matches, the term's index in `terms_lower` and how often it occurs in the entry's name
+    /// (its last segment, for a structural term). Filter-only matches have no hits.
+    pub fn term_hits(&self, path: &Path, depth: usize) -> Vec<(usize, usize)> {
+        let Some(base) = path.file_name() else {
+            return Vec::new();
+        };
+        let name_lower = self.fold(&base.to_string_lossy());
+        self.terms_lower
+            .iter()
+            .enumerate()
+            .filter(|(_, term)| term_matches(term, &name_lower, path, depth, self))
+            .map(|(index, term)| {
+                let needle = term.rsplit('/').next().unwrap_or(term);
+                // A term matched only within `max_distance` occurs zero times verbatim.
+                let count = if needle.is_empty() { 1 } else { name_lower.matches(needle).count().max(1) };
+                (index, count)
+            })
+            .collect()
+    }
+
+    /// Returns the total number of edits the terms that the entry at `path` matches are away
+    /// from its name (their last segments, for structural terms): 0 unless
+    /// [`ScanOptions::max_distance`] let some match approximately.
+    pub fn edit_distance(&self, path: &Path, depth: usize) -> usize {
+        let (Some(max), Some(base)) = (self.max_distance, path.file_name()) else {
+            return 0;
+        };
+        let name_lower = self.fold(&base.to_string_lossy());
+        self.terms_lower
+            .iter()
+            .filter(|term| term_matches(term, &name_lower, path, depth, self))
+            .filter_map(|term| closest_substring(&name_lower, term.rsplit('/').next().unwrap_or(term), max))
+            .map(|(_, _, distance)| distance)
+            .sum()
+    }
+
+    /// Whether `--larger-than` or `--smaller-than` is in effect.
+    fn sized(&self) -> bool {
+        self.larger_than.is_some() || self.smaller_than.is_some()
+    }
+
+    /// Whether `--name-longer-than` or `--name-shorter-than` is in effect.
+    fn name_bounded(&self) -> bool {
+        self.name_longer_than.is_some() || self.name_shorter_than.is_some()
+    }
+
+    /// Whether `--min-lines` or `--max-lines` is in effect.
+    fn line_bounded(&self) -> bool {
+        self.min_lines.is_some() || self.max_lines.is_some()
+    }
+
+    /// Returns the format `root` is read as when it is an archive: [`ScanOptions::archive`], or
+    /// else the one its extension names if it is a file (symlinks followed) in [`ScanOptions::files`].
+    pub fn archive_format(&self, root: &Path) -> Option<ArchiveFormat> {
+        self.archive.or_else(|| {
+            ArchiveFormat::from_path(root)
+                .filter(|_| self.files.target_metadata(root).is_ok_and(|metadata| metadata.kind == EntryKind::File))
+        })
+    }
+
+    /// Lower-cases a directory name unless case-sensitive (and brings it to NFC when
+    /// normalizing, or strips its diacritics when ignoring accents) for comparison against
+    /// `terms_lower`.
+    pub fn fold(&self, name: &str) -> String {
+        if self.ignore_accents {
+            return fold_with_offsets(name, true, !self.case_sensitive).0;
+        }
+        let name = if self.case_sensitive { Cow::Borrowed(name) } else { Cow::Owned(name.to_lowercase()) };
+        if self.normalize {
+            name.nfc().collect()
+        } else {
+            name.into_owned()
+        }
+    }
+
+    /// Lower-cases a name already passed through [`ScanOptions::fold`] when the terms are
+    /// case-sensitive, for the patterns that always ignore case.
+    fn lower_folded<'n>(&self, name: &'n str) -> Cow<'n, str> {
+        if self.case_sensitive {
+            Cow::Owned(name.to_lowercase())
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+}
+
+/// Where [`scan_dir`] lists a directory from.
+enum Source<'c> {
+    /// The filesystem, through the directory's `--cache` listing when there is one.
+    Disk(Option<&'c mut Option<CachedDir>>),
+    /// A directory inside an archive, whose entries were all read up front.
+    Archive(&'c mut CachedDir),
+}
+
+/// Recursively scans a directory (up to `opts.max_depth`) and builds a list of children along with a score.
+///
+/// For each directory:
+/// - It recurses only when `depth < opts.max_depth`.
+/// - Adds 1 to the score if the directory matches (see [`is_match`]), plus the scores of any
+///   matching descendants. Files and symlinks are candidates too when `opts.types` admits them.
+/// - Only includes directories that either match or have matching descendants, unless
+///   `opts.show_empty` asks to keep everything within depth.
+/// - Skips entries matched by the `.xtreeignore` files in `ignores` (outermost first) or in
+///   `path` itself.
+/// - Lists `path` from its cached listing when `source` has one that is still current,
+///   refreshing it otherwise, or from the archive listing `source` holds. Inside archives,
+///   nothing else is read from disk: no ignore files, permissions or devices.
+/// - Records in `report` the directories that cannot be read, treating them as empty, how many
+///   were listed and the deepest level reached.
+fn scan_dir(
+    path: &Path,
+    depth: usize,
+    opts: &ScanOptions,
+    ignores: &mut Vec<IgnoreFile>,
+    source: Source,
+    report: &mut ScanReport,
+) -> Result<(Vec<Tree>, u32), ScanError> {
+    if depth >= opts.max_depth {
+        report.capped = true;
+        return Ok((Vec::new(), 0));
+    }
+    if opts.scan_budget.is_some_and(|budget| report.scanned >= budget) {
+        report.over_budget = true;
+        return Ok((Vec::new(), 0));
+    }
+
+    let mut total_score = 0;
+    let mut children = Vec::new();
+
+    let in_archive = matches!(source, Source::Archive(_));
+    let own_ignore = if opts.xtreeignore && !in_archive { IgnoreFile::load(&*opts.files, path) } else { None };
+    let pushed_ignore = own_ignore.is_some();
+    ignores.extend(own_ignore);
+
+    let listing: Vec<(OsString, EntryKind, Source)> = match source {
+        Source::Archive(dir) => dir
+            .entries
+            .iter_mut()
+            .map(|entry| {
+                let dir = entry.dir.get_or_insert_with(CachedDir::default);
+                (OsString::from(&entry.name), entry.kind, Source::Archive(dir))
+            })
+            .collect(),
+        Source::Disk(cache) => match cache.and_then(|slot| cache::refresh(slot, &extended_path(path))) {
+            Some(cached) => cached
+                .entries
+                .iter_mut()
+                .map(|entry| (OsString::from(&entry.name), entry.kind, Source::Disk(Some(&mut entry.dir))))
+                .collect(),
+            None => match opts.files.read_dir(path) {
+                Ok(entries) => entries.into_iter().map(|(name, kind)| (name, kind, Source::Disk(None))).collect(),
+                Err(err) if opts.strict => {
+                    return Err(ScanError {
+                        path: path.to_path_buf(),
+                        source: err,
+                    })
+                }
+                Err(err) => {
+                    warn!("cannot read {}: {}", path.display(), err);
+                    report.skipped.push(path.to_path_buf());
+                    Vec::new()
+                }
+            },
+        },
+    };
+    report.scanned += 1;
+    if !listing.is_empty() {
+        report.deepest = report.deepest.max(depth + 1);
+    }
+    for (file_name, kind, child_source) in listing {
+        let entry_path = path.join(&file_name);
+        if !ignores.is_empty() && !opts.includes(&file_name) && is_ignored(ignores.iter(), &entry_path, kind == EntryKind::Dir) {
+            debug!("skipping {}: ignored by an ignore file", entry_path.display());
+            continue;
+        }
+        let candidate = opts.types.contains(&kind);
+        if kind != EntryKind::Dir && !candidate {
+            continue;
+        }
+        let name = file_name.to_string_lossy().into_owned();
+        let name_lower = opts.fold(&name);
+        let smart_ignored = kind == EntryKind::Dir && !in_archive && opts.smart_ignored(&file_name);
+        let (child_children, child_score) =
+            if kind != EntryKind::Dir || smart_ignored || (!in_archive && crosses_device(&entry_path, opts)) {
+                (Vec::new(), 0)
+            } else {
+                scan_dir(&entry_path, depth + 1, opts, ignores, child_source, report)?
+            };
+        let found = if kind == EntryKind::Dir && opts.only_dirs_with_files {
+            holds_matching_file(&child_children)
+        } else {
+            candidate && is_match(&name_lower, &entry_path, depth + 1, opts)
+        };
+        if found {
+            trace!("{} matches at depth {}", entry_path.display(), depth + 1);
+        }
+        // If the entry matches, count it.
+        let score_here = if found { 1 } else { 0 };
+
+        // Only include this entry if it or one of its descendants matches, or it was collapsed.
+        if found || child_score > 0 || opts.show_empty || smart_ignored {
+            let perms = if opts.perms && !in_archive {
+                opts.files.metadata(&entry_path).ok().and_then(|metadata| metadata.mode).map(mode_string)
+            } else {
+                None
+            };
+            let labels = if found { labels_for(&name_lower, opts) } else { Vec::new() };
+            children.push(Tree {
+                id: String::new(),
+                name,
+                kind,
+                matched: found,
+                score: score_here + child_score,
+                perms,
+                labels,
+                subdirs: opts.min_children.filter(|_| found).map(|_| count_subdirs(&entry_path, opts)),
+                size: (found && opts.sized()).then(|| usage(&*opts.files, &entry_path)),
+                name_len: (found && opts.name_bounded()).then(|| name_len(&entry_path)),
+                lines: if found && opts.line_bounded() { line_count(&*opts.files, &entry_path) } else { None },
+                mtime: if opts.mtimes && !in_archive { mtime_secs(&*opts.files, &entry_path) } else { None },
+                collapsed: None,
+                ignored: smart_ignored.then(|| count_entries(&*opts.files, &entry_path)),
+                roots: None,
+                children: child_children,
+            });
+        }
+        total_score += score_here + child_score;
+    }
+    if pushed_ignore {
+        ignores.pop();
+    }
+    // Once a sibling reaches the threshold, weaker branches are only incidental noise. Inside a
+    // subtree where no child reaches it on its own, every match is kept.
+    if opts.min_score > 1 && !opts.show_empty && children.iter().any(|child| child.score >= opts.min_score) {
+        children.retain(|child| child.score >= opts.min_score);
+    }
+    Ok((children, total_score))
+}
+
+/// Builds the filtered directory tree starting at `dirpath`, which may also be an archive (see
+/// [`ScanOptions::archive_format`]) whose entries are then scanned instead.
+///
+/// When `opts.count_root` is true, the basename of `dirpath` is tested against the search terms
+/// and counted like any other directory.
+///
+/// With `opts.min_score` above 1, only branches whose total score meets the threshold are kept;
+/// the matches inside such a dense branch are all shown even when each scores less on its own.
+///
+/// Returns `None` if no directory (including subdirectories) matches, or the whole tree scores
+/// below `opts.min_score`; with `opts.strict`, also when a directory cannot be read.
+pub fn build_tree_dict(dirpath: &str, opts: &ScanOptions) -> Option<Tree> {
+    build_tree_dict_with_report(dirpath, opts, &mut ScanReport::default()).ok().flatten()
+}
+
+/// What a scan by [`build_tree_dict_with_report`] ran into, besides the tree itself.
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    /// Directories that could not be read (e.g. for lack of permission) and were scanned as if
+    /// empty.
+    pub skipped: Vec<PathBuf>,
+    /// Deepest level any entry was found at; the root's entries are at depth 1.
+    pub deepest: usize,
+    /// Whether some directory sat at [`ScanOptions::max_depth`] and was not descended into.
+    pub capped: bool,
+    /// Directories listed, counting the unreadable ones in `skipped`.
+    pub scanned: usize,
+    /// Whether [`ScanOptions::scan_budget`] ran out with directories left to list, which were
+    /// scanned as if empty.
+    pub over_budget: bool,
+}
+
+/// Like [`build_tree_dict`], additionally recording in `report` the directories that could not
+/// be read and how deep the scan went. With `opts.strict`, the first unreadable directory ends
+/// the scan with an error instead.
+pub fn build_tree_dict_with_report(
+    dirpath: &str,
+    opts: &ScanOptions,
+    report: &mut ScanReport,
+) -> Result<Option<Tree>, ScanError> {
+    let path = Path::new(dirpath);
+    let (children, mut score) = match opts.archive_format(path) {
+        Some(format) => match archive::load(path, format) {
+            Ok(mut listing) => scan_dir(path, 0, opts, &mut Vec::new(), Source::Archive(&mut listing), report)?,
+            Err(err) if opts.strict => {
+                return Err(ScanError {
+                    path: path.to_path_buf(),
+                    source: err,
+                })
+            }
+            Err(err) => {
+                warn!("cannot read archive {}: {}", path.display(), err);
+                report.skipped.push(path.to_path_buf());
+                (Vec::new(), 0)
+            }
+        },
+        None => {
+            let mut cached = if opts.cache { cache::load(path) } else { None };
+            let source = Source::Disk(opts.cache.then_some(&mut cached));
+            let scanned = scan_dir(path, 0, opts, &mut Vec::new(), source, report);
+            if let Some(cached) = cached {
+                // The cache only saves time; failing to write it is not worth failing the search.
+                let _ = cache::store(path, cached);
+            }
+            scanned?
+        }
+    };
+    let matched = opts.count_root
+        && if opts.only_dirs_with_files { holds_matching_file(&children) } else { root_matches(path, opts) };
+    if matched {
+        score += 1;
+    }
+    if score == 0 || score < opts.min_score {
+        Ok(None)
+    } else {
+        let perms = if opts.perms {
+            opts.files.target_metadata(path).ok().and_then(|metadata| metadata.mode).map(mode_string)
+        } else {
+            None
+        };
+        let labels = match path.file_name() {
+            Some(base) if matched => labels_for(&opts.fold(&base.to_string_lossy()), opts),
+            _ => Vec::new(),
+        };
+        let mut tree = Tree {
+            id: String::new(),
+            name: dirpath.to_string(),
+            kind: EntryKind::Dir,
+            matched,
+            score,
+            perms,
+            labels,
+            subdirs: opts.min_children.filter(|_| matched).map(|_| count_subdirs(path, opts)),
+            size: (matched && opts.sized()).then(|| usage(&*opts.files, path)),
+            name_len: (matched && opts.name_bounded()).then(|| name_len(path)),
+            lines: None,
+            mtime: opts.mtimes.then(|| mtime_secs(&*opts.files, path)).flatten(),
+            collapsed: None,
+            ignored: None,
+            roots: None,
+            children,
+        };
+        assign_ids(&mut tree, &mut PathBuf::new());
+        Ok(Some(tree))
+    }
+}
+
+/// Sets the [`Tree::id`] of `tree`, found at `relative` below the root, and its descendants.
+fn assign_ids(tree: &mut Tree, relative: &mut PathBuf) {
+    tree.id = node_id(relative);
+    for child in &mut tree.children {
+        relative.push(&child.name);
+        assign_ids(child, relative);
+        relative.pop();
+    }
+}
+
+/// Returns a stable identifier for the entry at `relative` below the scanned root (the root
+/// itself is the empty path): 16 hex digits of the 64-bit FNV-1a hash of its components joined
+/// by `/`. The same relative path gets the same id on every platform and run, so snapshots of
+/// a tree can be compared node by node.
+pub fn node_id(relative: &Path) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (index, component) in relative.iter().enumerate() {
+        let bytes = component.to_string_lossy();
+        let separator: &[u8] = if index == 0 { b"" } else { b"/" };
+        for &byte in separator.iter().chain(bytes.as_bytes()) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// A directory that could not be read during a `--strict` scan (see [`ScanOptions::strict`]).
+#[derive(Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub source: io::Error,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot read {}: {}", self.path.display(), self.source)
+    }
+}
+
+impl Error for ScanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// A matching directory (or, with [`ScanOptions::types`], file or symlink) yielded by [`matches`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedDir {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Depth below the root; the root itself is at depth 0.
+    pub depth: usize,
+    /// Number of distinct search terms the directory name contains (1 for a filter-only match).
+    pub score: u32,
+}
+
+/// An entry visited by [`walk`], matching or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisitInfo {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Depth below the root; the root itself is at depth 0.
+    pub depth: usize,
+    /// Number of distinct search terms the name contains (1 for a filter-only match), or 0 when
+    /// the entry does not match.
+    pub score: u32,
+    pub matched: bool,
+}
+
+/// What a [`walk`] visitor asks for next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// Go on with the next entry.
+    Continue,
+    /// End the walk; nothing further is read.
+    Stop,
+}
+
+/// Lazy, depth-first iterator over matching directories, created by [`matches`].
+pub struct Matches<'a> {
+    opts: &'a ScanOptions,
+    /// Pending root visit, produced before any descendant.
+    root: Option<VisitInfo>,
+    /// Open directory listings, innermost last.
+    stack: Vec<Frame>,
+    /// The unreadable directory that ended a strict walk.
+    error: Option<ScanError>,
+}
+
+/// A directory being listed by [`Matches`].
+struct Frame {
+    entries: std::vec::IntoIter<(OsString, EntryKind)>,
+    /// Depth of the entries this listing yields.
+    depth: usize,
+    /// The directory's path as the caller spelled it.
+    dir: PathBuf,
+    /// The directory's own `.xtreeignore`, if any.
+    ignore: Option<IgnoreFile>,
+}
+
+impl Frame {
+    fn open(dir: PathBuf, depth: usize, opts: &ScanOptions) -> Result<Frame, ScanError> {
+        let entries = match opts.files.read_dir(&dir) {
+            Ok(entries) => entries.into_iter(),
+            Err(source) => return Err(ScanError { path: dir, source }),
+        };
+        let ignore = if opts.xtreeignore { IgnoreFile::load(&*opts.files, &dir) } else { None };
+        Ok(Frame {
+            entries,
+            depth,
+            dir,
+            ignore,
+        })
+    }
+}
+
+/// Returns an iterator over the directories under `root` whose names match `opts`, in
+/// depth-first (pre-order) order.
+///
+/// Unlike [`build_tree_dict`], nothing is collected up front: directories are read only as the
+/// iterator advances, so consumers can stop early without paying for the rest of the walk.
+/// Unreadable directories are skipped, or with [`ScanOptions::strict`] end the iteration; see
+/// [`Matches::take_error`].
+///
+/// ```no_run
+/// use std::path::Path;
+/// use xtree::{matches, ScanOptions};
+///
+/// let opts = ScanOptions::new(["src"]);
+/// if let Some(first) = matches(Path::new("."), &opts).next() {
+///     println!("{} (depth {})", first.path.display(), first.depth);
+/// }
+/// ```
+pub fn matches<'a>(root: &Path, opts: &'a ScanOptions) -> Matches<'a> {
+    let root_visit = (opts.count_root && opts.types.contains(&EntryKind::Dir)).then(|| {
+        let score = root
+            .file_name()
+            .map_or(0, |base| match_score(&opts.fold(&base.to_string_lossy()), root, 0, opts));
+        VisitInfo {
+            path: root.to_path_buf(),
+            kind: EntryKind::Dir,
+            depth: 0,
+            score,
+            matched: score > 0,
+        }
+    });
+    let (stack, error) = match opts.max_depth {
+        0 => (Vec::new(), None),
+        _ => match Frame::open(root.to_path_buf(), 1, opts) {
+            Ok(frame) => (vec![frame], None),
+            Err(err) => (Vec::new(), opts.strict.then_some(err)),
+        },
+    };
+    Matches {
+        opts,
+        root: root_visit,
+        stack,
+        error,
+    }
+}
+
+/// Walks the tree under `root` depth-first like [`matches`], calling `visitor` for every entry
+/// of a kind in [`ScanOptions::types`], matching or not (check [`VisitInfo::matched`]). The root
+/// is visited first when [`ScanOptions::count_root`] is set. Returning [`Visit::Stop`] ends the
+/// walk right away. With [`ScanOptions::strict`], an unreadable directory ends it with an error.
+///
+/// ```no_run
+/// use std::path::Path;
+/// use xtree::{walk, ScanOptions, Visit};
+///
+/// # fn main() -> Result<(), xtree::ScanError> {
+/// let opts = ScanOptions::new(["test"]);
+/// let (mut seen, mut found) = (0, Vec::new());
+/// walk(Path::new("."), &opts, |info| {
+///     seen += 1;
+///     if info.matched {
+///         found.push(info.path.clone());
+///     }
+///     if found.len() == 10 { Visit::Stop } else { Visit::Continue }
+/// })?;
+/// println!("{} of {} directories match", found.len(), seen);
+/// # Ok(())
+/// # }
+/// ```
+pub fn walk<F>(root: &Path, opts: &ScanOptions, mut visitor: F) -> Result<(), ScanError>
+where
+    F: FnMut(&VisitInfo) -> Visit,
+{
+    let mut walker = matches(root, opts);
+    while let Some(info) = walker.next_visit() {
+        if visitor(&info) == Visit::Stop {
+            return Ok(());
+        }
+    }
+    walker.take_error().map_or(Ok(()), Err)
+}
+
+impl Matches<'_> {
+    /// Returns the directory that could not be read if a [`ScanOptions::strict`] walk ended
+    /// early because of it; check once the iterator is exhausted.
+    pub fn take_error(&mut self) -> Option<ScanError> {
+        self.error.take()
+    }
+
+    /// Advances to the next entry of a kind in `opts.types`, whether it matches or not.
+    fn next_visit(&mut self) -> Option<VisitInfo> {
+        if let Some(root) = self.root.take() {
+            return Some(root);
+        }
+        loop {
+            let frame = self.stack.last_mut()?;
+            let depth = frame.depth;
+            let Some((file_name, kind)) = frame.entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let path = frame.dir.join(&file_name);
+            if !self.opts.includes(&file_name)
+                && is_ignored(self.stack.iter().filter_map(|frame| frame.ignore.as_ref()), &path, kind == EntryKind::Dir)
+            {
+                continue;
+            }
+
+            if kind == EntryKind::Dir
+                && depth < self.opts.max_depth
+                && !self.opts.smart_ignored(&file_name)
+                && !crosses_device(&path, self.opts)
+            {
+                match Frame::open(path.clone(), depth + 1, self.opts) {
+                    Ok(child) => self.stack.push(child),
+                    Err(err) if self.opts.strict => {
+                        self.stack.clear();
+                        self.error = Some(err);
+                    }
+                    Err(_) => {}
+                }
+            }
+            if !self.opts.types.contains(&kind) {
+                continue;
+            }
+            let name_lower = self.opts.fold(&file_name.to_string_lossy());
+            let score = match_score(&name_lower, &path, depth, self.opts);
+            return Some(VisitInfo {
+                path,
+                kind,
+                depth,
+                score,
+                matched: score > 0,
+            });
+        }
+    }
+}
+
+impl Iterator for Matches<'_> {
+    type Item = MatchedDir;
+
+    fn next(&mut self) -> Option<MatchedDir> {
+        loop {
+            let info = self.next_visit()?;
+            if info.matched {
+                return Some(MatchedDir {
+                    path: info.path,
+                    kind: info.kind,
+                    depth: info.depth,
+                    score: info.score,
+                });
+            }
+        }
+    }
+}
+
+/// Converts `path` to its extended-length form (`\\?\C:\...`, or `\\?\UNC\server\share\...`
+/// for UNC roots) so that directories nested beyond `MAX_PATH` can still be read. Only the
+/// paths handed to the filesystem use this form; everything displayed keeps the caller's spelling.
+#[cfg(windows)]
+pub(crate) fn extended_path(path: &Path) -> Cow<'_, Path> {
+    let absolute = match std::path::absolute(path) {
+        Ok(absolute) => absolute,
+        Err(_) => return Cow::Borrowed(path),
+    };
+    let raw = absolute.as_os_str().to_string_lossy();
+    if raw.starts_with(r"\\?\") {
+        Cow::Owned(absolute)
+    } else if let Some(unc) = raw.strip_prefix(r"\\") {
+        Cow::Owned(PathBuf::from(format!(r"\\?\UNC\{}", unc)))
+    } else {
+        Cow::Owned(PathBuf::from(format!(r"\\?\{}", raw)))
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) fn extended_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Returns true if a file or symlink among `children`, the entries found in one directory,
+/// matches, as [`ScanOptions::only_dirs_with_files`] wants of the directory.
+fn holds_matching_file(children: &[Tree]) -> bool {
+    children.iter().any(|child| child.kind != EntryKind::Dir && child.matched)
+}
+
+/// Returns true if the basename of the root `path` matches.
+fn root_matches(path: &Path, opts: &ScanOptions) -> bool {
+    opts.types.contains(&EntryKind::Dir)
+        && path
+            .file_name()
+            .is_some_and(|base| is_match(&opts.fold(&base.to_string_lossy()), path, 0, opts))
+}
+
+/// Returns true if an entry's name contains any of the search terms (every one with
+/// `opts.match_all`; when there are any), none of the excluded terms, and satisfies the filter
+/// expression, the name length bounds, the `min_children` threshold, `empty`, the extended
+/// attribute, the owner and the size bounds (when set).
+fn is_match(name_lower: &str, path: &Path, depth: usize, opts: &ScanOptions) -> bool {
+    let terms_ok = if opts.terms_lower.is_empty() {
+        opts.all
+            || opts.filter.is_some()
+            || opts.min_children.is_some()
+            || opts.empty
+            || opts.sized()
+            || opts.name_bounded()
+            || opts.line_bounded()
+            || opts.xattr.is_some()
+            || opts.owner.is_some()
+            || opts.group.is_some()
+            || !opts.excluded.is_empty()
+    } else if opts.match_all {
+        opts.terms_lower.iter().all(|term| term_matches(term, name_lower, path, depth, opts))
+    } else {
+        matches_any(name_lower, path, depth, opts)
+    };
+    terms_ok
+        && !opts.excluded.iter().any(|term| term_matches(term, name_lower, path, depth, opts))
+        && opts.changed.as_ref().is_none_or(|changed| changed.contains(&relative_tail(path, depth)))
+        && opts.filter.as_ref().is_none_or(|filter| {
+            filter.eval(&Candidate {
+                name_lower: &opts.lower_folded(name_lower),
+                path,
+                depth,
+                files: &*opts.files,
+            })
+        })
+        && (!opts.name_bounded() || {
+            let len = name_len(path);
+            opts.name_longer_than.is_none_or(|min| len > min) && opts.name_shorter_than.is_none_or(|max| len < max)
+        })
+        && opts.min_children.is_none_or(|min| count_subdirs(path, opts) >= min)
+        && (!opts.empty || is_empty_dir(path, opts))
+        && opts.xattr.as_ref().is_none_or(|(name, value)| has_xattr(&*opts.files, path, name, value.as_deref()))
+        && (opts.owner.is_none() && opts.group.is_none() || owned_by(&*opts.files, path, opts.owner, opts.group))
+        // Last, as they read the whole file or subtree.
+        && (!opts.line_bounded()
+            || line_count(&*opts.files, path).is_some_and(|lines| {
+                opts.min_lines.is_none_or(|min| lines >= min) && opts.max_lines.is_none_or(|max| lines <= max)
+            }))
+        && (!opts.sized() || {
+            let size = usage(&*opts.files, path);
+            opts.larger_than.is_none_or(|min| size > min) && opts.smaller_than.is_none_or(|max| size < max)
+        })
+}
+
+/// Returns the number of immediate entries of the directory at `path`, with one listing and
+/// without descending; unreadable directories count as empty.
+fn count_entries(files: &dyn FileSource, path: &Path) -> u64 {
+    files.read_dir(path).map_or(0, |entries| entries.len() as u64)
+}
+
+/// Returns the number of lines of the regular file at `path`, the last one counting even
+/// without a trailing newline, or `None` for other entries, unreadable files and binary ones
+/// (with a NUL byte). The contents are read from `files` in chunks.
+fn line_count(files: &dyn FileSource, path: &Path) -> Option<u64> {
+    if files.file_type(path).ok()? != EntryKind::File {
+        return None;
+    }
+    let mut file = files.open(path).ok()?;
+    let mut chunk = [0; 64 * 1024];
+    let (mut lines, mut last) = (0, b'\n');
+    loop {
+        let read = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        };
+        let chunk = &chunk[..read];
+        if chunk.contains(&0) {
+            return None;
+        }
+        lines += chunk.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        last = chunk[read - 1];
+    }
+    Some(lines + u64::from(last != b'\n'))
+}
+
+/// Returns the size in bytes of the file at `path`, or for a directory the sum of every file in
+/// its full subtree, regardless of the scan depth, ignore files or what matched. Symlinks count
+/// as their own size and are not followed; unreadable parts count as empty.
+pub fn disk_usage(path: &Path) -> u64 {
+    usage(&OsFiles, path)
+}
+
+/// [`disk_usage`], read through `files`.
+fn usage(files: &dyn FileSource, path: &Path) -> u64 {
+    let Ok(metadata) = files.metadata(path) else {
+        return 0;
+    };
+    if metadata.kind != EntryKind::Dir {
+        return metadata.len;
+    }
+    files.read_dir(path).map_or(0, |entries| {
+        entries.into_iter().map(|(name, _)| usage(files, &path.join(name))).sum()
+    })
+}
+
+/// Returns the length of the last component of `path` in Unicode scalar values.
+fn name_len(path: &Path) -> usize {
+    path.file_name().map_or(0, |name| name.to_string_lossy().chars().count())
+}
+
+/// Returns the modification time of the entry at `path` in seconds since the Unix epoch.
+fn mtime_secs(files: &dyn FileSource, path: &Path) -> Option<u64> {
+    let modified = files.metadata(path).ok()?.modified?;
+    modified.duration_since(UNIX_EPOCH).ok().map(|age| age.as_secs())
+}
+
+/// Whether this platform can read extended attributes, so [`ScanOptions::xattr`] takes effect.
+#[cfg(unix)]
+pub const XATTR_SUPPORTED: bool = xattr::SUPPORTED_PLATFORM;
+/// Whether this platform can read extended attributes, so [`ScanOptions::xattr`] takes effect.
+#[cfg(not(unix))]
+pub const XATTR_SUPPORTED: bool = false;
+
+/// Returns true if the entry at `path` (not following symlinks) has the extended attribute
+/// `name`, set to `value` when one is given. A filesystem without extended attributes has none;
+/// a platform without them lets every entry through.
+fn has_xattr(files: &dyn FileSource, path: &Path, name: &str, value: Option<&str>) -> bool {
+    match files.xattr(path, name) {
+        Ok(Some(found)) => value.is_none_or(|value| found == value.as_bytes()),
+        Ok(None) => false,
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => true,
+        Err(err) => {
+            debug!("cannot read extended attributes of {}: {}", path.display(), err);
+            false
+        }
+    }
+}
+
+/// Returns true if the entry at `path` (not following symlinks) belongs to the user `owner` and
+/// the group `group`, each when given. An entry whose metadata cannot be read matches neither;
+/// ids the platform does not expose match any.
+fn owned_by(files: &dyn FileSource, path: &Path, owner: Option<u32>, group: Option<u32>) -> bool {
+    let is = |id: Option<u32>, wanted: Option<u32>| wanted.is_none_or(|wanted| id.is_none_or(|id| id == wanted));
+    files
+        .metadata(path)
+        .is_ok_and(|metadata| is(metadata.uid, owner) && is(metadata.gid, group))
+}
+
+/// Returns true if `path` is a directory without any entries, hidden or ignored ones included.
+fn is_empty_dir(path: &Path, opts: &ScanOptions) -> bool {
+    opts.files.read_dir(path).is_ok_and(|entries| entries.is_empty())
+}
+
+/// Counts the immediate subdirectories of `path` (symlinks not followed); 0 for anything that
+/// cannot be listed.
+fn count_subdirs(path: &Path, opts: &ScanOptions) -> usize {
+    opts.files.read_dir(path).map_or(0, |entries| {
+        entries.into_iter().filter(|(_, kind)| *kind == EntryKind::Dir).count()
+    })
+}
+
+/// Returns the labels whose patterns `name_lower` contains, each once, in manifest order.
+fn labels_for(name_lower: &str, opts: &ScanOptions) -> Vec<String> {
+    let name_lower = opts.lower_folded(name_lower);
+    let mut labels: Vec<String> = Vec::new();
+    for (pattern, label) in &opts.labels {
+        if name_lower.contains(pattern.as_str()) && !labels.contains(label) {
+            labels.push(label.clone());
+        }
+    }
+    labels
+}
+
+/// Returns the last `depth` components of `path`: its path relative to the scan root, whatever
+/// form the root was spelled in.
+fn relative_tail(path: &Path, depth: usize) -> PathBuf {
+    let mut components: Vec<_> = path.components().rev().take(depth).collect();
+    components.reverse();
+    components.iter().collect()
+}
+
+/// Scores a directory for [`matches`]: the number of distinct terms it contains, or 1 for a
+/// filter-only match, or 0 when it does not match.
+fn match_score(name_lower: &str, path: &Path, depth: usize, opts: &ScanOptions) -> u32 {
+    if is_match(name_lower, path, depth, opts) {
+        term_score(name_lower, path, depth, opts).max(1)
+    } else {
+        0
+    }
+}
+
+/// Returns true when `--same-filesystem` is active and `path` lives on another device.
+/// Like `find -xdev`, such a mount point is still matched but never descended into.
+fn crosses_device(path: &Path, opts: &ScanOptions) -> bool {
+    let crosses = opts.root_dev.is_some()
+        && !on_same_device(opts.root_dev, opts.files.metadata(path).ok().and_then(|metadata| metadata.dev));
+    if crosses {
+        debug!("not descending into {}: on another filesystem", path.display());
+    }
+    crosses
+}
+
+/// Formats the permission bits `mode` like `ls -l` does (e.g. `rwxr-xr-x`).
+#[cfg(unix)]
+fn mode_string(mode: u32) -> String {
+    // (read, write, execute, special bit, special char when executable)
+    let classes = [
+        (0o400, 0o200, 0o100, 0o4000, 's'),
+        (0o040, 0o020, 0o010, 0o2000, 's'),
+        (0o004, 0o002, 0o001, 0o1000, 't'),
+    ];
+    let mut out = String::with_capacity(9);
+    for (r, w, x, special, special_char) in classes {
+        out.push(if mode & r != 0 { 'r' } else { '-' });
+        out.push(if mode & w != 0 { 'w' } else { '-' });
+        out.push(match (mode & x != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    out
+}
+
+/// Without Unix mode bits, only the read-only flag is available.
+#[cfg(not(unix))]
+fn mode_string(mode: u32) -> String {
+    if mode & 0o222 == 0 { "readonly" } else { "writable" }.to_string()
+}
+
+/// Returns false only when both device ids are known and differ.
+fn on_same_device(root_dev: Option<u64>, dev: Option<u64>) -> bool {
+    match (root_dev, dev) {
+        (Some(root_dev), Some(dev)) => root_dev == dev,
+        _ => true,
+    }
+}
+
+/// Returns true if the entry matches any of the (lower-cased) search terms.
+fn matches_any(name_lower: &str, path: &Path, depth: usize, opts: &ScanOptions) -> bool {
+    opts.terms_lower.iter().any(|term| term_matches(term, name_lower, path, depth, opts))
+}
+
+/// Counts how many of the (lower-cased) search terms the entry matches.
+fn term_score(name_lower: &str, path: &Path, depth: usize, opts: &ScanOptions) -> u32 {
+    opts.terms_lower
+        .iter()
+        .filter(|term| term_matches(term, name_lower, path, depth, opts))
+        .count() as u32
+}
+
+/// Tests one search term against an entry at `depth` below the root.
+///
+/// A plain term must occur in the name. A term with slashes is structural: its last segment
+/// must occur in the name and the others in the names of the entry's ancestors, each segment
+/// again as a substring. `src/test` asks for the segments in order anywhere above the entry
+/// (`src/a/b/test` matches); a leading slash anchors them at the root, one per level, so
+/// `/src/test` only matches `test` directly inside a top-level `src`.
+fn term_matches(term: &str, name_lower: &str, path: &Path, depth: usize, opts: &ScanOptions) -> bool {
+    let Some((ancestors, last)) = term.rsplit_once('/') else {
+        return occurs(name_lower, term, opts.max_distance);
+    };
+    if !occurs(name_lower, last, opts.max_distance) {
+        return false;
+    }
+    let wanted: Vec<&str> = ancestors.split('/').filter(|segment| !segment.is_empty()).collect();
+    let relative = relative_tail(path, depth);
+    let mut parents: Vec<String> = relative
+        .components()
+        .map(|component| opts.fold(&component.as_os_str().to_string_lossy()))
+        .collect();
+    parents.pop();
+    if term.starts_with('/') {
+        parents.len() == wanted.len()
+            && parents.iter().zip(&wanted).all(|(parent, segment)| occurs(parent, segment, opts.max_distance))
+    } else {
+        let mut parents = parents.iter();
+        wanted.iter().all(|segment| parents.any(|parent| occurs(parent, segment, opts.max_distance)))
+    }
+}
+
+/// Whether `segment` occurs in `name`: as a substring or, with `max_distance`, within that many
+/// edits of one.
+fn occurs(name: &str, segment: &str, max_distance: Option<usize>) -> bool {
+    name.contains(segment) || max_distance.is_some_and(|max| closest_substring(name, segment, max).is_some())
+}
+
+/// Finds the part of `haystack` closest to `needle` in Levenshtein distance (insertions,
+/// deletions and substitutions of characters), if it is at most `max` edits away. Returns its
+/// byte range and the distance; of equally close parts, the one ending first wins.
+fn closest_substring(haystack: &str, needle: &str, max: usize) -> Option<(usize, usize, usize)> {
+    let hay: Vec<(usize, char)> = haystack.char_indices().collect();
+    let pattern: Vec<char> = needle.chars().collect();
+    let (n, m) = (pattern.len(), hay.len());
+    // dist[i][j]: fewest edits turning pattern[..i] into a part of the haystack ending at j.
+    // Row 0 is all zeros, so a match may start anywhere.
+    let mut dist = vec![vec![0; m + 1]; n + 1];
+    let cost = |i: usize, j: usize| usize::from(pattern[i - 1] != hay[j - 1].1);
+    for i in 1..=n {
+        dist[i][0] = i;
+        for j in 1..=m {
+            dist[i][j] = (dist[i - 1][j - 1] + cost(i, j)).min(dist[i - 1][j] + 1).min(dist[i][j - 1] + 1);
+        }
+    }
+    let (end, &distance) = dist[n].iter().enumerate().min_by_key(|&(_, distance)| *distance)?;
+    if distance > max {
+        return None;
+    }
+    // Retrace the edits back to where the part starts.
+    let (mut i, mut start) = (n, end);
+    while i > 0 {
+        if start > 0 && dist[i][start] == dist[i - 1][start - 1] + cost(i, start) {
+            i -= 1;
+            start -= 1;
+        } else if dist[i][start] == dist[i - 1][start] + 1 {
+            i -= 1;
+        } else {
+            start -= 1;
+        }
+    }
+    let offset = |j: usize| hay.get(j).map_or(haystack.len(), |&(at, _)| at);
+    Some((offset(start), offset(end), distance))
+}
+
+/// Brings `s` to Unicode NFC form. Composed and decomposed text render identically, so this is
+/// how names are displayed when normalizing, keeping highlight offsets aligned with the terms.
+pub fn nfc(s: &str) -> String {
+    s.nfc().collect()
+}
+
+/// Lower-cases `s` (when `lowercase` is set) and, when `strip_accents` is set, removes its
+/// diacritics (decomposing it and dropping the combining marks). Alongside the folded string,
+/// returns for every byte offset of it (plus one past the end) the offset in `s` of the
+/// character it came from, so that a match found in the folded text can be mapped back to a
+/// span of the original.
+fn fold_with_offsets(s: &str, strip_accents: bool, lowercase: bool) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(s.len());
+    let mut offsets = Vec::with_capacity(s.len() + 1);
+    let mut push = |c: char, from: usize, folded: &mut String| {
+        folded.push(c);
+        offsets.extend(std::iter::repeat_n(from, c.len_utf8()));
+    };
+    let mut fold_char = |c: char, from: usize, folded: &mut String| {
+        if strip_accents {
+            decompose_canonical(c, |part| {
+                if !is_combining_mark(part) {
+                    push(part, from, folded);
+                }
+            });
+        } else {
+            push(c, from, folded);
+        }
+    };
+    for (from, c) in s.char_indices() {
+        if lowercase {
+            for lower in c.to_lowercase() {
+                fold_char(lower, from, &mut folded);
+            }
+        } else {
+            fold_char(c, from, &mut folded);
+        }
+    }
+    offsets.push(s.len());
+    (folded, offsets)
+}
+
+/// Maps an end offset in folded text back to `s`: the end of the original character holding the
+/// last folded byte, extended over any combining marks that were dropped after it.
+fn original_end(s: &str, offsets: &[usize], end: usize) -> usize {
+    match offsets.get(end) {
+        Some(&next) if end > 0 && next == offsets[end - 1] => {
+            // `end` splits the expansion of one character; cover that whole character.
+            s[next..].chars().next().map_or(next, |c| next + c.len_utf8())
+        }
+        Some(&next) => next,
+        None => s.len(),
+    }
+}
+
+/// Highlights the first occurrence of each search term in `s`, giving every term its own
+/// ANSI color. When occurrences overlap, the earliest (then longest) one wins.
+///
+/// Terms are found case-insensitively, but what is colored is always the original text of `s`:
+/// removing the escape sequences gives `s` back, in every matching mode.
+pub fn highlight_substring(s: &str, terms_lower: &[String]) -> String {
+    highlight_folded(s, terms_lower, false)
+}
+
+/// Like [`highlight_substring`], but when `ignore_accents` is set, terms are found in `s` with
+/// its diacritics stripped (see [`ScanOptions::set_ignore_accents`]) while the original
+/// characters, accents included, are the ones colored. Structural terms (`src/test`) highlight
+/// their last segment.
+pub fn highlight_folded(s: &str, terms_lower: &[String], ignore_accents: bool) -> String {
+    let style = HighlightStyle {
+        ignore_accents,
+        ..HighlightStyle::default()
+    };
+    highlight_styled(s, terms_lower, &style)
+}
+
+/// How [`highlight_styled`] finds and colors the search terms.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct HighlightStyle {
+    /// Find terms with diacritics stripped, as [`highlight_folded`] does.
+    pub ignore_accents: bool,
+    /// Only find terms with the same case (see [`ScanOptions::new_case_sensitive`]); when
+    /// false, terms kept in their case for a case-sensitive match are found regardless of it.
+    pub case_sensitive: bool,
+    /// ANSI background color (40–47, or 100–107 for the bright ones) behind every highlight.
+    pub background: Option<u8>,
+    /// Also find terms within this many edits, as [`ScanOptions::max_distance`] matches them.
+    pub max_distance: Option<usize>,
+}
+
+/// Returns the escape sequence that starts highlighting the term at `index`: its color from
+/// [`HIGHLIGHT_COLORS`], on `background` when there is one.
+pub fn highlight_sgr(index: usize, background: Option<u8>) -> String {
+    let (color, _) = HIGHLIGHT_COLORS[index % HIGHLIGHT_COLORS.len()];
+    match background {
+        Some(background) => format!("{};{}m", color.trim_end_matches('m'), background),
+        None => color.to_string(),
+    }
+}
+
+/// Where [`highlight_styled`] highlights a term: the byte range `start..end` of the original
+/// string, and the index of the term in `terms_lower`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HighlightSpan {
+    pub start: usize,
+    pub end: usize,
+    pub term: usize,
+}
+
+/// Returns the spans [`highlight_styled`] colors in `s`, in order: the first occurrence of each
+/// term, leaving out any that overlaps an earlier, longer one.
+pub fn highlight_spans(s: &str, terms_lower: &[String], style: &HighlightStyle) -> Vec<HighlightSpan> {
+    let (folded, offsets) = fold_with_offsets(s, style.ignore_accents, !style.case_sensitive);
+    let mut spans: Vec<(usize, usize, usize)> = terms_lower
+        .iter()
+        .map(|term| term.rsplit('/').next().unwrap_or(term))
+        .map(|term| if style.case_sensitive { Cow::Borrowed(term) } else { Cow::Owned(term.to_lowercase()) })
+        .enumerate()
+        .filter(|(_, term)| !term.is_empty())
+        .filter_map(|(i, term)| {
+            let term = term.as_ref();
+            let (pos, end) = match folded.find(term) {
+                Some(pos) => (pos, pos + term.len()),
+                None => {
+                    let (pos, end, _) = closest_substring(&folded, term, style.max_distance?)?;
+                    (pos, end)
+                }
+            };
+            Some((offsets[pos], original_end(s, &offsets, end), i))
+        })
+        .collect();
+    spans.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+    let mut kept = Vec::with_capacity(spans.len());
+    let mut last = 0;
+    for (start, end, term) in spans {
+        if start >= last && s.get(start..end).is_some() {
+            kept.push(HighlightSpan { start, end, term });
+            last = end;
+        }
+    }
+    kept
+}
+
+/// Like [`highlight_folded`], with the matching and colors of `style`.
+pub fn highlight_styled(s: &str, terms_lower: &[String], style: &HighlightStyle) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut last = 0;
+    for span in highlight_spans(s, terms_lower, style) {
+        out.push_str(&s[last..span.start]);
+        out.push_str(&highlight_sgr(span.term, style.background));
+        out.push_str(&s[span.start..span.end]);
+        out.push_str("\x1b[0m");
+        last = span.end;
+    }
+    out.push_str(&s[last..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Options for `terms` that scan an in-memory tree of the directories `dirs`.
+    fn in_memory(dirs: &[&str], terms: &[&str]) -> ScanOptions {
+        let mut files = MemoryFiles::new();
+        for dir in dirs {
+            files.add_dir(dir);
+        }
+        scanning(files, terms)
+    }
+
+    /// Options for `terms` that scan `files`.
+    fn scanning(files: MemoryFiles, terms: &[&str]) -> ScanOptions {
+        let mut opts = ScanOptions::new(terms);
+        opts.files = Arc::new(files);
+        opts
+    }
+
+    /// The matches below `root`, relative to it with `/` separators, sorted.
+    fn found(opts: &ScanOptions) -> Vec<String> {
+        let mut found: Vec<String> = matches(Path::new("root"), opts)
+            .filter_map(|dir| Some(dir.path.strip_prefix("root").ok()?.to_string_lossy().replace('\\', "/")))
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn same_device_compares_known_ids() {
+        assert!(on_same_device(Some(1), Some(1)));
+        assert!(!on_same_device(Some(1), Some(2)));
+    }
+
+    #[test]
+    fn accent_folding_highlights_the_original_characters() {
+        let terms = vec!["cafe".to_string()];
+        assert_eq!(highlight_folded("Le Café", &terms, true), "Le \x1b[91mCafé\x1b[0m");
+        assert_eq!(highlight_folded("cafe\u{301}s", &terms, true), "\x1b[91mcafe\u{301}\x1b[0ms");
+        assert_eq!(highlight_folded("Le Café", &terms, false), "Le Café");
+        let terms = vec!["e".to_string()];
+        assert_eq!(highlight_folded("xÉy", &terms, true), "x\x1b[91mÉ\x1b[0my");
+    }
+
+    #[test]
+    fn highlights_keep_the_original_text() {
+        let plain = |s: &str| s.replace("\x1b[91m", "").replace("\x1b[92m", "").replace("\x1b[0m", "");
+        let terms = vec!["myapp".to_string()];
+        let highlighted = highlight_substring("MyAppServer", &terms);
+        assert_eq!(highlighted, "\x1b[91mMyApp\x1b[0mServer");
+
+        let fuzzy = HighlightStyle {
+            max_distance: Some(1),
+            ..HighlightStyle::default()
+        };
+        let close = highlight_styled("MyAbpServer", &terms, &fuzzy);
+        assert_eq!(close, "\x1b[91mMyAbp\x1b[0mServer");
+
+        let terms = vec!["creme".to_string(), "brulee".to_string()];
+        let folded = highlight_folded("CRÈME Brûlée", &terms, true);
+        assert_eq!(folded, "\x1b[91mCRÈME\x1b[0m \x1b[92mBrûlée\x1b[0m");
+
+        let terms = vec!["src/MyApp".to_lowercase()];
+        let structural = highlight_substring("MyAppTests", &terms);
+        assert_eq!(structural, "\x1b[91mMyApp\x1b[0mTests");
+
+        for (highlighted, original) in
+            [(highlighted, "MyAppServer"), (close, "MyAbpServer"), (folded, "CRÈME Brûlée"), (structural, "MyAppTests")]
+        {
+            assert_eq!(plain(&highlighted), original);
+        }
+    }
+
+    #[test]
+    fn background_joins_the_term_color() {
+        let terms = vec!["a".to_string(), "b".to_string()];
+        let style = HighlightStyle {
+            background: Some(44),
+            ..HighlightStyle::default()
+        };
+        assert_eq!(highlight_styled("ab", &terms, &style), "\x1b[91;44ma\x1b[0m\x1b[92;44mb\x1b[0m");
+    }
+
+    #[test]
+    fn case_sensitive_terms_keep_their_case() {
+        let opts = ScanOptions::new_case_sensitive(["Src"]);
+        assert_eq!(opts.terms_lower, ["Src"]);
+        assert!(matches_any(&opts.fold("MySrc"), Path::new("MySrc"), 1, &opts));
+        assert!(!matches_any(&opts.fold("src"), Path::new("src"), 1, &opts));
+        let terms = vec!["Src".to_string()];
+        let style = HighlightStyle {
+            case_sensitive: true,
+            ..HighlightStyle::default()
+        };
+        assert_eq!(highlight_styled("src-Src", &terms, &style), "src-\x1b[91mSrc\x1b[0m");
+        let style = HighlightStyle::default();
+        assert_eq!(highlight_styled("src-Src", &terms, &style), "\x1b[91msrc\x1b[0m-Src");
+    }
+
+    #[test]
+    fn excluded_terms_rule_out_any_or_all_matches() {
+        let matched = |opts: &ScanOptions, name: &str| is_match(&opts.fold(name), Path::new(name), 1, opts);
+        let mut opts = ScanOptions::new(["foo", "bar"]);
+        opts.excluded = vec!["old".to_string()];
+        assert!(matched(&opts, "foo"));
+        assert!(matched(&opts, "bar-x"));
+        assert!(!matched(&opts, "foo-old"));
+        opts.match_all = true;
+        assert!(!matched(&opts, "foo"));
+        assert!(matched(&opts, "foo-bar"));
+        assert!(!matched(&opts, "Foo-Bar-Old"));
+        let mut opts = ScanOptions::new(Vec::<String>::new());
+        opts.excluded = vec!["old".to_string()];
+        assert!(matched(&opts, "anything"));
+        assert!(!matched(&opts, "old"));
+    }
+
+    #[test]
+    fn merge_unions_children_by_name() {
+        let opts = in_memory(&["a/src/test", "a/x-test", "b/src/test", "b/docs/test"], &["test"]);
+        let scan = |root: &str| build_tree_dict(root, &opts).expect("matches");
+        let mut merged = scan("a");
+        merged.merge(scan("b"));
+        let names: Vec<&str> = merged.children.iter().map(|child| child.name.as_str()).collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.contains(&"src") && names.contains(&"x-test") && names.contains(&"docs"));
+        let src = merged.children.iter().find(|child| child.name == "src").expect("src");
+        assert_eq!((src.roots, src.score), (Some(2), 2));
+        assert_eq!(src.children[0].roots, Some(2));
+        assert_eq!(merged.count_matches(), 3);
+    }
+
+    #[test]
+    fn closest_substring_tolerates_edits() {
+        assert_eq!(closest_substring("my-confguration", "configuration", 1), Some((3, 15, 1)));
+        assert_eq!(closest_substring("configuration", "configuration", 0), Some((0, 13, 0)));
+        assert_eq!(closest_substring("conf", "configuration", 2), None);
+        let mut opts = ScanOptions::new(["configuration"]);
+        assert!(!matches_any("confguration", Path::new("confguration"), 1, &opts));
+        opts.max_distance = Some(1);
+        assert!(matches_any("confguration", Path::new("confguration"), 1, &opts));
+        let style = HighlightStyle {
+            max_distance: Some(1),
+            ..HighlightStyle::default()
+        };
+        let terms = vec!["configuration".to_string()];
+        assert_eq!(highlight_styled("my-Confguration", &terms, &style), "my-\x1b[91mConfguration\x1b[0m");
+    }
+
+    #[test]
+    fn walk_visits_every_directory_until_stopped() {
+        let opts = in_memory(&["root/a/test/b"], &["test"]);
+        let mut visited = Vec::new();
+        walk(Path::new("root"), &opts, |info| {
+            visited.push((info.depth, info.matched));
+            Visit::Continue
+        })
+        .expect("walk");
+        visited.sort();
+        assert_eq!(visited, [(0, false), (1, false), (2, true), (3, false)]);
+
+        let mut seen = 0;
+        walk(Path::new("root"), &opts, |info| {
+            seen += 1;
+            if info.matched { Visit::Stop } else { Visit::Continue }
+        })
+        .expect("walk");
+        assert_eq!(seen, 3);
+    }
+
+    #[test]
+    fn memory_files_answer_size_and_shape_checks() {
+        let mut files = MemoryFiles::new();
+        files
+            .add_file("root/big/a.bin", 600)
+            .add_file("root/big/sub/b.bin", 500)
+            .add_file("root/small/c.bin", 10)
+            .add_dir("root/hollow")
+            .add_dir("root/wide/x")
+            .add_dir("root/wide/y");
+        let mut opts = ScanOptions::new(Vec::<String>::new());
+        opts.files = Arc::new(files);
+        opts.count_root = false;
+        let found = |opts: &ScanOptions| -> Vec<PathBuf> {
+            let mut found: Vec<PathBuf> = matches(Path::new("root"), opts).map(|dir| dir.path).collect();
+            found.sort();
+            found
+        };
+        assert_eq!(disk_usage(Path::new("root/big")), 0);
+        assert_eq!(usage(&*opts.files, Path::new("root/big")), 1100);
+        opts.larger_than = Some(1000);
+        assert_eq!(found(&opts), [PathBuf::from("root/big")]);
+        opts.larger_than = None;
+        opts.empty = true;
+        let empty: Vec<PathBuf> = ["root/hollow", "root/wide/x", "root/wide/y"].iter().map(PathBuf::from).collect();
+        assert_eq!(found(&opts), empty);
+        opts.empty = false;
+        opts.min_children = Some(2);
+        assert_eq!(found(&opts), [PathBuf::from("root/wide")]);
+    }
+
+    #[test]
+    fn depth_and_type_limit_what_matches() {
+        let mut files = MemoryFiles::new();
+        files.add_dir("root/a/b/c/target").add_file("root/notes-dir/notes.txt", 0);
+        let mut opts = scanning(files, &["target", "notes"]);
+        assert_eq!(found(&opts), ["notes-dir"]);
+        opts.max_depth = 4;
+        assert_eq!(found(&opts), ["a/b/c/target", "notes-dir"]);
+        opts.types = vec![EntryKind::File];
+        assert_eq!(found(&opts), ["notes-dir/notes.txt"]);
+    }
+
+    #[test]
+    fn slash_terms_match_ancestors_and_anchor_at_the_root() {
+        let opts = in_memory(&["root/src/test", "root/src/a/test", "root/lib/src/test"], &["src/test"]);
+        assert_eq!(found(&opts), ["lib/src/test", "src/a/test", "src/test"]);
+        let opts = in_memory(&["root/src/test", "root/lib/src/test"], &["/src/test"]);
+        assert_eq!(found(&opts), ["src/test"]);
+    }
+
+    #[test]
+    fn not_terms_rule_matches_out() {
+        let dirs = ["root/foo", "root/foo-bar", "root/foo-old", "root/bar-old"];
+        let mut opts = in_memory(&dirs, &["foo", "bar"]);
+        opts.excluded = vec!["old".to_string()];
+        assert_eq!(found(&opts), ["foo", "foo-bar"]);
+        opts.match_all = true;
+        assert_eq!(found(&opts), ["foo-bar"]);
+        opts.terms_lower.clear();
+        opts.count_root = false;
+        assert_eq!(found(&opts), ["foo", "foo-bar"]);
+    }
+
+    #[test]
+    fn ignore_files_hide_directories_unless_included() {
+        let mut files = MemoryFiles::new();
+        files.add_dir("root/.git/hooks").add_dir("root/app/hooks").add_file_with_contents("root/.xtreeignore", ".git\n");
+        let mut opts = scanning(files, &["hooks"]);
+        assert_eq!(found(&opts), ["app/hooks"]);
+        opts.include = vec![".git".to_string()];
+        assert_eq!(found(&opts), [".git/hooks", "app/hooks"]);
+    }
+
+    #[test]
+    fn trailing_slash_patterns_hide_directories_only() {
+        let mut files = MemoryFiles::new();
+        files
+            .add_dir("root/app/logs")
+            .add_file("root/src/logs", 0)
+            .add_file_with_contents("root/.xtreeignore", "logs/\n");
+        let mut opts = scanning(files, &["logs"]);
+        opts.types = vec![EntryKind::File, EntryKind::Dir];
+        assert_eq!(found(&opts), ["src/logs"]);
+    }
+
+    #[test]
+    fn empty_matches_directories_without_entries() {
+        let mut files = MemoryFiles::new();
+        files.add_dir("root/stale").add_dir("root/used/sub").add_file("root/kept/file", 0);
+        let mut opts = scanning(files, &[]);
+        opts.empty = true;
+        assert_eq!(found(&opts), ["stale", "used/sub"]);
+    }
+
+    #[test]
+    fn typos_and_name_lengths_match_by_characters() {
+        let mut opts = in_memory(&["root/confguration", "root/configuration", "root/other"], &["configuration"]);
+        assert_eq!(found(&opts), ["configuration"]);
+        opts.max_distance = Some(1);
+        assert_eq!(found(&opts), ["confguration", "configuration"]);
+
+        let mut opts = in_memory(&["root/short", "root/a-rather-long-name", "root/ééééééé"], &[]);
+        opts.count_root = false;
+        opts.name_longer_than = Some(6);
+        assert_eq!(found(&opts), ["a-rather-long-name", "ééééééé"]);
+        opts.name_shorter_than = Some(10);
+        assert_eq!(found(&opts), ["ééééééé"]);
+    }
+
+    #[test]
+    fn normalize_matches_decomposed_names() {
+        let mut opts = in_memory(&["root/cafe\u{301}-menu"], &["caf\u{e9}"]);
+        opts.set_normalize(false);
+        assert!(found(&opts).is_empty());
+        opts.set_normalize(true);
+        assert_eq!(found(&opts), ["cafe\u{301}-menu"]);
+    }
+
+    #[test]
+    fn owners_and_extended_attributes_come_from_the_source() {
+        let mut files = MemoryFiles::new();
+        files
+            .add_dir("root/mine/src")
+            .add_dir("root/theirs/src")
+            .set_owner("root/mine/src", 1000, 100)
+            .set_owner("root/theirs/src", 1001, 100)
+            .set_xattr("root/theirs/src", "user.xtree", "red");
+        let mut opts = scanning(files, &["src"]);
+        opts.owner = Some(1000);
+        assert_eq!(found(&opts), ["mine/src"]);
+        opts.owner = None;
+        opts.group = Some(100);
+        assert_eq!(found(&opts), ["mine/src", "theirs/src"]);
+        opts.group = None;
+        opts.xattr = Some(("user.xtree".to_string(), None));
+        assert_eq!(found(&opts), ["theirs/src"]);
+        opts.xattr = Some(("user.xtree".to_string(), Some("blue".to_string())));
+        assert!(found(&opts).is_empty());
+    }
+
+    #[test]
+    fn permissions_and_devices_come_from_the_source() {
+        let mut files = MemoryFiles::new();
+        files
+            .add_dir("root/local/src")
+            .add_dir("root/mnt/src")
+            .set_mode("root", 0o755)
+            .set_mode("root/local", 0o700)
+            .set_device("root", 1)
+            .set_device("root/local", 1)
+            .set_device("root/mnt", 2);
+        let mut opts = scanning(files, &["src"]);
+        opts.root_dev = Some(1);
+        assert_eq!(found(&opts), ["local/src"]);
+
+        opts.perms = true;
+        let tree = build_tree_dict("root", &opts).expect("matches");
+        assert_eq!(tree.perms.as_deref(), Some(mode_string(0o755).as_str()));
+        assert_eq!(tree.children[0].perms.as_deref(), Some(mode_string(0o700).as_str()));
+    }
+
+    #[test]
+    fn line_bounds_read_contents_from_the_source() {
+        let mut files = MemoryFiles::new();
+        files
+            .add_file_with_contents("root/src/long.rs", "a\nb\nc")
+            .add_file_with_contents("root/src/short.rs", "a\n")
+            .add_file("root/src/empty.rs", 0)
+            .add_file_with_contents("root/src/binary.rs", "a\0\nb\nc\n");
+        assert_eq!(line_count(&files, Path::new("root/src/long.rs")), Some(3));
+        assert_eq!(line_count(&files, Path::new("root/src/binary.rs")), None);
+        assert_eq!(line_count(&files, Path::new("root/src")), None);
+        let mut opts = scanning(files, &["rs"]);
+        opts.types = vec![EntryKind::File];
+        opts.min_lines = Some(2);
+        assert_eq!(found(&opts), ["src/long.rs"]);
+        opts.min_lines = None;
+        opts.max_lines = Some(1);
+        assert_eq!(found(&opts), ["src/empty.rs", "src/short.rs"]);
+    }
+
+    #[test]
+    fn min_score_prunes_branches_with_fewer_matches() {
+        let mut opts = in_memory(&["root/a/x1", "root/a/x2", "root/b/x3"], &["x"]);
+        opts.min_score = 2;
+        let tree = build_tree_dict("root", &opts).expect("matches");
+        let kept: Vec<&str> = tree.children.iter().map(|child| child.name.as_str()).collect();
+        assert_eq!(kept, ["a"]);
+        assert_eq!(tree.children[0].children.len(), 2);
+        opts.min_score = 4;
+        assert!(build_tree_dict("root", &opts).is_none());
+    }
+
+    #[test]
+    fn strict_scan_fails_on_unreadable_directory() {
+        let missing = PathBuf::from("root/gone");
+        let mut opts = in_memory(&["root"], &["test"]);
+        opts.count_root = false;
+        assert!(walk(&missing, &opts, |_| Visit::Continue).is_ok());
+        let mut report = ScanReport::default();
+        let tree = build_tree_dict_with_report(&missing.to_string_lossy(), &opts, &mut report);
+        assert!(matches!(tree, Ok(None)));
+        assert_eq!(report.skipped.len(), 1);
+
+        opts.strict = true;
+        let err = walk(&missing, &opts, |_| Visit::Continue).expect_err("strict walk");
+        assert_eq!(err.path, missing);
+        let err = build_tree_dict_with_report(&missing.to_string_lossy(), &opts, &mut ScanReport::default())
+            .expect_err("strict scan");
+        assert_eq!(err.source.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn node_ids_hash_relative_paths_the_same_everywhere() {
+        // FNV-1a of the empty string, then of "a/b".
+        assert_eq!(node_id(Path::new("")), "cbf29ce484222325");
+        assert_eq!(node_id(&Path::new("a").join("b")), node_id(Path::new("a/b")));
+        assert_ne!(node_id(Path::new("a/b")), node_id(Path::new("ab")));
+    }
+
+    #[test]
+    fn archive_roots_are_detected_in_the_file_source() {
+        let mut files = MemoryFiles::new();
+        files.add_file("root.zip", 0).add_dir("dir.tar");
+        let opts = scanning(files, &["x"]);
+        assert_eq!(opts.archive_format(Path::new("root.zip")), Some(ArchiveFormat::Zip));
+        assert_eq!(opts.archive_format(Path::new("dir.tar")), None);
+        assert_eq!(opts.archive_format(Path::new("missing.zip")), None);
+    }
+
+    #[test]
+    fn same_device_allows_unknown_ids() {
+        assert!(on_same_device(None, Some(2)));
+        assert!(on_same_device(Some(1), None));
+        assert!(on_same_device(None, None));
+    }
+}
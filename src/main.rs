@@ -1,180 +1,3425 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::path::Path;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use clap::{App, Arg};
+use clap_complete::Shell;
+use log::{debug, LevelFilter};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use xtree::{
+    build_tree_dict_with_report, clear_cache, ArchiveFormat, FileSource, highlight_sgr, highlight_spans, highlight_styled, matches, nfc, node_id, walk, EntryKind, Filter, HighlightStyle, ScanError, ScanOptions, ScanReport, Tree, Visit, HIGHLIGHT_COLORS, SMART_IGNORE_DIRS, XATTR_SUPPORTED,
+};
 
-/// A simple tree structure representing a directory and its matching subdirectories.
+/// Settings that control how the tree is rendered.
+#[derive(Serialize)]
+struct PrintOptions<'a> {
+    #[serde(skip)]
+    terms_lower: &'a [String],
+    /// File colors from `LS_COLORS`, when set and colors are on.
+    #[serde(skip)]
+    ls_colors: Option<LsColors>,
+    /// `tree`, `json`, `ndjson`, `yaml` or `org`.
+    format: &'a str,
+    /// Indent JSON output.
+    json_pretty: bool,
+    /// List matches level by level (`--breadth-first`).
+    breadth_first: bool,
+    /// Print a color key after the tree.
+    legend: bool,
+    /// `--replace FROM TO`, with `FROM` lower-cased: matches are shown with their new names.
+    replace: Option<(&'a str, &'a str)>,
+    /// Print per-depth match counts after the tree.
+    histogram: bool,
+    /// List only the deepest matches of each branch, as paths (`--tips`).
+    tips: bool,
+    /// List all matches as paths, most relevant first (`--rank`).
+    rank: bool,
+    /// List matches as paths in the style of grep (`--grep`).
+    grep: bool,
+    /// List matches as their chains of ancestors, joined by this separator (`--breadcrumbs`).
+    breadcrumbs: Option<&'a str>,
+    /// List only this many matches as paths, most recently modified first (`--recent`).
+    recent: Option<usize>,
+    /// Emit ANSI escapes, resolved from [`ColorChoice`]; nothing prints any when this is off.
+    color: bool,
+    /// Indent with two spaces per level instead of drawing connectors.
+    indent_only: bool,
+    /// Draw connectors and separators with ASCII characters only (`--ascii`).
+    ascii: bool,
+    /// Mirror the tree for right-to-left reading (`--rtl`): names first, connectors to their right.
+    rtl: bool,
+    /// Color each connector by its level (`--depth-colors`): `absolute`, or `relative` to the
+    /// shallowest match of its branch. Only set with colors on.
+    depth_colors: Option<&'a str>,
+    /// Print the footer line; off with `--no-summary`.
+    summary: bool,
+    /// Template for the footer line, see [`format_summary`].
+    summary_format: &'a str,
+    /// Per-node line template (`--template`), replacing the built-in layout.
+    template: Option<Template>,
+    /// Non-matching directories are kept and dimmed (`--show-empty`).
+    show_empty: bool,
+    /// Display names in NFC form so highlights line up with normalized terms.
+    normalize: bool,
+    /// Deepest level drawn in the tree (`--display-depth`); deeper matches are only counted.
+    display_depth: Option<usize>,
+    /// Level whose nodes absorb the matches below them into a count (`--flatten-depth`).
+    flatten_depth: Option<usize>,
+    /// Draw only the matches and the chains of directories leading to them (`--summary-tree`).
+    summary_tree: bool,
+    /// Draw only the first this many matches at each depth (`--sample`).
+    sample: Option<usize>,
+    /// Link every name to its directory with OSC 8 escapes; only on a terminal with colors.
+    hyperlinks: bool,
+    /// Cut lines longer than this many columns (`--max-width`).
+    max_width: Option<usize>,
+    /// What the footer counts.
+    count: Count,
+    /// Matching files shown per directory before the rest are summed up (`--max-matches-per-dir`).
+    max_matches_per_dir: Option<usize>,
+    /// How highlights are found, like the scan matched them, and colored (`--highlight-bg`).
+    highlight: HighlightStyle,
+    /// Do not report unreadable directories on stderr (`--quiet-errors`).
+    quiet_errors: bool,
+    /// Tell on stderr how deep the scan went (`--report-depth`).
+    report_depth: bool,
+    /// File the scan statistics are written to as JSON (`--stats-json`).
+    stats_json: Option<&'a str>,
+    /// Absolute base that printed paths are made relative to (`--relative-to`).
+    relative_to: Option<PathBuf>,
+    /// Address the `ndjson` stream is sent to instead of stdout (`--socket`).
+    socket: Option<&'a str>,
+    /// Directories scanned and overlaid into one tree (`--merge-roots`).
+    merge_roots: Vec<&'a str>,
+    /// Hide the names of directories that did not match (`--anonymize`).
+    anonymize: bool,
+    /// Order of each directory's children (`--sort`): `mtime` or `mtime-desc`.
+    sort: Option<&'a str>,
+    /// Reverse the order of each directory's children (`--reverse`), after any `--sort`.
+    reverse: bool,
+}
+
+/// Exit status when the scan succeeded but nothing matched; matches exit with 0.
+const EXIT_NO_MATCH: i32 = 1;
+/// Exit status for errors: unreadable directory, bad arguments, I/O failures.
+const EXIT_ERROR: i32 = 2;
+
+/// A scan of a filesystem root at least this deep asks for confirmation first (`--yes`).
+const LARGE_SCAN_DEPTH: usize = 5;
+
+/// Default footer, e.g. `3 directories`.
+const DEFAULT_SUMMARY_FORMAT: &str = "{count} {directory|directories}";
+
+/// Default footer when files or symlinks can match too.
+const MIXED_SUMMARY_FORMAT: &str = "{count} {match|matches}";
+
+/// Default footer under `--count files`.
+const FILE_SUMMARY_FORMAT: &str = "{count} {file|files}";
+
+/// Which matches the footer counts (`--count`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Count {
+    Dirs,
+    /// Files and symlinks.
+    Files,
+    All,
+}
+
+impl Count {
+    fn includes(self, kind: EntryKind) -> bool {
+        match self {
+            Count::Dirs => kind == EntryKind::Dir,
+            Count::Files => kind != EntryKind::Dir,
+            Count::All => true,
+        }
+    }
+
+    /// Counts the matches in `tree`, itself included, of the kinds this selects.
+    fn tally(self, tree: &Tree) -> u32 {
+        if self == Count::All {
+            return tree.count_matches();
+        }
+        u32::from(tree.matched && self.includes(tree.kind)) + tree.children.iter().map(|child| self.tally(child)).sum::<u32>()
+    }
+}
+
+/// Lines of tree, `--grep` and `--breadcrumbs` output buffered before they are flushed; about a
+/// screenful, so a pager gets the first page right away without a write per line.
+const FLUSH_LINES: usize = 64;
+
+/// Buffers output and flushes it every [`FLUSH_LINES`] lines.
+struct LineFlusher<W: Write> {
+    inner: BufWriter<W>,
+    /// Lines written since the last flush.
+    pending: usize,
+}
+
+impl<W: Write> LineFlusher<W> {
+    fn new(inner: W) -> Self {
+        LineFlusher {
+            inner: BufWriter::new(inner),
+            pending: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for LineFlusher<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.pending += buf[..written].iter().filter(|&&byte| byte == b'\n').count();
+        if self.pending >= FLUSH_LINES {
+            self.flush()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pending = 0;
+        self.inner.flush()
+    }
+}
+
+/// Expands a summary template: `{count}` becomes the number of matches and `{one|many}` picks
+/// `one` when the count is exactly 1 and `many` otherwise. Anything else is kept verbatim.
+fn format_summary(format: &str, count: u32) -> String {
+    let mut out = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            break;
+        };
+        out.push_str(&rest[..open]);
+        let inner = &rest[open + 1..close];
+        if inner == "count" {
+            out.push_str(&count.to_string());
+        } else if let Some((one, many)) = inner.split_once('|') {
+            out.push_str(if count == 1 { one } else { many });
+        } else {
+            out.push_str(&rest[open..=close]);
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns the first `{...}` of a summary template that is neither `{count}` nor `{one|many}`.
+fn unknown_summary_placeholder(format: &str) -> Option<&str> {
+    let mut rest = format;
+    while let Some(open) = rest.find('{') {
+        let close = open + rest[open..].find('}')?;
+        let inner = &rest[open + 1..close];
+        if inner != "count" && !inner.contains('|') {
+            return Some(&rest[open..=close]);
+        }
+        rest = &rest[close + 1..];
+    }
+    None
+}
+
+/// A parsed `--template`: literal text interleaved with per-node placeholders.
 #[derive(Debug)]
-struct Tree {
-    name: String,
-    children: Vec<Tree>,
+struct Template {
+    pieces: Vec<Piece>,
+    source: String,
 }
 
-/// Recursively scans a directory (up to `max_depth`) and builds a list of children along with a score.
-///
-/// For each directory:
-/// - It recurses only when `depth < max_depth`.
-/// - Adds 1 to the score if the directory name (case‑insensitive) contains the search term,
-///   plus the scores of any matching descendants.
-/// - Only includes directories that either match or have matching descendants.
-fn scan_dir(path: &Path, depth: usize, max_depth: usize, searchterm_lower: &str) -> (Vec<Tree>, u32) {
-    if depth >= max_depth {
-        return (Vec::new(), 0);
-    }
-
-    let mut total_score = 0;
-    let mut children = Vec::new();
-
-    if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries.flatten() {
-            if let Ok(file_type) = entry.file_type() {
-                if file_type.is_dir() {
-                    let name = entry.file_name().to_string_lossy().into_owned();
-                    let name_lower = name.to_lowercase();
-                    let (child_children, child_score) =
-                        scan_dir(&entry.path(), depth + 1, max_depth, searchterm_lower);
-                    let found = name_lower.contains(searchterm_lower);
-                    // If the directory name contains the term, count it.
-                    let score_here = if found { 1 } else { 0 };
-
-                    // Only include this directory if it or one of its descendants matches.
-                    if found || child_score > 0 {
-                        children.push(Tree {
-                            name,
-                            children: child_children,
-                        });
-                    }
-                    total_score += score_here + child_score;
+/// Serializes as the source template.
+impl Serialize for Template {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Name,
+    Path,
+    Depth,
+    Score,
+    Prefix,
+    Branch,
+}
+
+impl Template {
+    /// Parses a template such as `{prefix}{branch}{name} ({score})`; `{{` and `}}` stand for
+    /// literal braces. Unknown placeholders and unclosed braces are errors.
+    fn parse(source: &str) -> Result<Template, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut rest = source;
+        while let Some(c) = rest.chars().next() {
+            if let Some(tail) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+                text.push(c);
+                rest = tail;
+                continue;
+            }
+            if c == '}' {
+                return Err("unmatched `}` (write `}}` for a literal brace)".to_string());
+            }
+            if c != '{' {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let close = rest.find('}').ok_or("unclosed `{` (write `{{` for a literal brace)")?;
+            let piece = match &rest[1..close] {
+                "name" => Piece::Name,
+                "path" => Piece::Path,
+                "depth" => Piece::Depth,
+                "score" => Piece::Score,
+                "prefix" => Piece::Prefix,
+                "branch" => Piece::Branch,
+                other => {
+                    return Err(format!(
+                        "unknown placeholder `{{{}}}` (expected name, path, depth, score, prefix or branch)",
+                        other
+                    ))
                 }
+            };
+            if !text.is_empty() {
+                pieces.push(Piece::Text(std::mem::take(&mut text)));
+            }
+            pieces.push(piece);
+            rest = &rest[close + 1..];
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Template {
+            pieces,
+            source: source.to_string(),
+        })
+    }
+}
+
+/// Spells `path` relative to `base` (absolute) when it lies below it, or else as an absolute
+/// path; without a base it is returned as is.
+fn relative_path(path: &Path, base: Option<&Path>) -> PathBuf {
+    let Some(base) = base else {
+        return path.to_path_buf();
+    };
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match absolute.strip_prefix(base) {
+        Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => absolute,
+    }
+}
+
+/// What a template line is built from: the styled name and where the node sits.
+struct Line<'a> {
+    name: String,
+    node: &'a Tree,
+    path: &'a Path,
+    depth: usize,
+    prefix: &'a str,
+    branch: &'a str,
+}
+
+/// Builds the output line for one node: `prefix`, `branch` and the annotated name, or whatever
+/// `--template` asks for.
+fn format_line(line: Line, popts: &PrintOptions) -> String {
+    let name = annotate(line.name, line.node);
+    let Some(template) = &popts.template else {
+        if popts.rtl {
+            // Isolated, so the bidi algorithm orders the name on its own, away from the connectors.
+            return format!("{}{}{}{}{}", FSI, name, PDI, line.branch, line.prefix);
+        }
+        return format!("{}{}{}", line.prefix, line.branch, name);
+    };
+    let mut out = String::new();
+    for piece in &template.pieces {
+        match piece {
+            Piece::Text(text) => out.push_str(text),
+            Piece::Name => out.push_str(&name),
+            Piece::Path => {
+                let path = relative_path(line.path, popts.relative_to.as_deref());
+                out.push_str(&path.display().to_string());
             }
+            Piece::Depth => out.push_str(&line.depth.to_string()),
+            Piece::Score => out.push_str(&line.node.score.to_string()),
+            Piece::Prefix => out.push_str(line.prefix),
+            Piece::Branch => out.push_str(line.branch),
         }
     }
-    (children, total_score)
+    out
 }
 
-/// Builds the filtered directory tree starting at `dirpath`.
+/// Whether to emit ANSI escapes, as asked for on the command line and in the environment.
 ///
-/// Returns `None` if no directory (including subdirectories) matches.
-fn build_tree_dict(dirpath: &str, searchterm_lower: &str, max_depth: usize) -> Option<Tree> {
-    let path = Path::new(dirpath);
-    let (children, score) = scan_dir(path, 0, max_depth, searchterm_lower);
-    if score == 0 {
-        None
+/// It is resolved once, in `main`, into [`PrintOptions::color`]; every renderer goes by that
+/// flag alone, so with colors off no escape sequence of any kind (highlights, dimming,
+/// `LS_COLORS`, hyperlinks, screen clearing) is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Reads the choice from `--color` and `--no-color`. `--no-color` and `NO_COLOR` always
+    /// win, then an explicit `--color always|never`.
+    fn from_flags(color: &str, no_color: bool) -> ColorChoice {
+        if no_color || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+            return ColorChoice::Never;
+        }
+        match color {
+            "always" => ColorChoice::Always,
+            "never" => ColorChoice::Never,
+            _ => ColorChoice::Auto,
+        }
+    }
+
+    /// Decides whether to emit colors. In `auto` mode colors are used on a terminal, and also
+    /// in CI environments whose log viewers render ANSI: GitHub Actions, or any CI that sets
+    /// `CI` together with a non-dumb `TERM`.
+    fn resolve(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stdout().is_terminal() || ci_supports_ansi(),
+        }
+    }
+}
+
+/// Returns true when known CI environment variables indicate that ANSI output is rendered.
+fn ci_supports_ansi() -> bool {
+    let term_set = env::var("TERM").is_ok_and(|term| !term.is_empty() && term != "dumb");
+    env::var_os("GITHUB_ACTIONS").is_some() || (env::var_os("CI").is_some() && term_set)
+}
+
+/// Splits `line` into its ANSI escape sequences (`true`) and visible characters (`false`).
+fn ansi_pieces(line: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = line;
+    std::iter::from_fn(move || {
+        let c = rest.chars().next()?;
+        let len = if let Some(csi) = rest.strip_prefix("\x1b[") {
+            // CSI sequences end with a byte in `@`..=`~`.
+            csi.find(|c| ('\x40'..='\x7e').contains(&c)).map_or(rest.len(), |end| end + 3)
+        } else if let Some(osc) = rest.strip_prefix("\x1b]") {
+            // OSC sequences (hyperlinks) end with `ESC \` or BEL.
+            match (osc.find("\x1b\\"), osc.find('\x07')) {
+                (Some(st), Some(bel)) if bel < st => bel + 3,
+                (Some(st), _) => st + 4,
+                (None, Some(bel)) => bel + 3,
+                (None, None) => rest.len(),
+            }
+        } else {
+            c.len_utf8()
+        };
+        let (piece, tail) = rest.split_at(len);
+        rest = tail;
+        Some((c == '\x1b' && len > 1, piece))
+    })
+}
+
+/// Cuts `line` to at most `max_width` visible characters, ending it with `…` when anything was
+/// cut. ANSI escape sequences take no width; a truncated colored line gets a closing reset so
+/// the color does not leak into the next one.
+fn fit_width(line: String, max_width: Option<usize>) -> String {
+    let Some(max_width) = max_width else {
+        return line;
+    };
+    if ansi_pieces(&line).filter(|(escape, _)| !escape).count() <= max_width {
+        return line;
+    }
+    let mut out = String::with_capacity(line.len());
+    let mut visible = 0;
+    for (escape, piece) in ansi_pieces(&line) {
+        if !escape {
+            if visible + 1 == max_width {
+                break;
+            }
+            visible += 1;
+        }
+        out.push_str(piece);
+    }
+    out.push('…');
+    if out.contains("\x1b[") {
+        out.push_str("\x1b[0m");
+    }
+    if out.contains(OSC8_START) {
+        out.push_str(OSC8_END);
+    }
+    out
+}
+
+const OSC8_START: &str = "\x1b]8;;";
+const OSC8_END: &str = "\x1b]8;;\x1b\\";
+
+/// Turns `path` into a `file://` URL, percent-encoding everything but unreserved characters
+/// and separators.
+fn file_url(path: &Path) -> String {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut url = String::from("file://");
+    let absolute = absolute.to_string_lossy().replace('\\', "/");
+    if !absolute.starts_with('/') {
+        // `C:/dir` becomes `file:///C:/dir`.
+        url.push('/');
+    }
+    for byte in absolute.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/:".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    url
+}
+
+/// Wraps a display name in an OSC 8 hyperlink to `path` when `--hyperlinks` is in effect.
+fn hyperlink(display_name: String, path: &Path, popts: &PrintOptions) -> String {
+    if popts.hyperlinks {
+        format!("{}{}\x1b\\{}{}", OSC8_START, file_url(path), display_name, OSC8_END)
     } else {
-        Some(Tree {
-            name: dirpath.to_string(),
-            children,
-        })
+        display_name
+    }
+}
+
+/// Appends the node's manifest labels, size, subdirectory count, permission string and collapsed
+/// matches, if any, to its display name: `vendor [vendored] [52 subdirs] [rwxr-xr-x] (+3 matches below)`.
+fn annotate(display_name: String, node: &Tree) -> String {
+    let mut out = display_name;
+    if !node.labels.is_empty() {
+        out = format!("{} [{}]", out, node.labels.join(", "));
+    }
+    if let Some(size) = node.size {
+        out = format!("{} [{}]", out, human_size(size));
+    }
+    if let Some(len) = node.name_len {
+        out = format!("{} [{} {}]", out, len, if len == 1 { "char" } else { "chars" });
+    }
+    if let Some(lines) = node.lines {
+        out = format!("{} [{} {}]", out, lines, if lines == 1 { "line" } else { "lines" });
+    }
+    if let Some(subdirs) = node.subdirs {
+        out = format!("{} [{} {}]", out, subdirs, if subdirs == 1 { "subdir" } else { "subdirs" });
+    }
+    if let Some(perms) = &node.perms {
+        out = format!("{} [{}]", out, perms);
+    }
+    if let Some(roots) = node.roots {
+        out = format!("{} [in {} roots]", out, roots);
+    }
+    if let Some(entries) = node.ignored {
+        out = format!("{} [ignored, {} top-level {}]", out, entries, if entries == 1 { "entry" } else { "entries" });
+    }
+    match node.collapsed {
+        Some(below) => format!("{} (+{} {} below)", out, below, if below == 1 { "match" } else { "matches" }),
+        None => out,
+    }
+}
+
+/// Replaces the name of every directory in `node` that did not match, the root included, with
+/// `dir_` and the first six digits of its id (`--anonymize`), so the same path always gets the
+/// same placeholder. Matches keep their names.
+fn anonymize(node: &mut Tree) {
+    if !node.matched {
+        node.name = format!("dir_{}", &node.id[..6]);
+    }
+    for child in &mut node.children {
+        anonymize(child);
+    }
+}
+
+/// Keeps the first `limit` matches at each depth below `node` (whose children are at `depth`),
+/// in drawing order, counting those seen so far per depth in `seen` (`--sample`). Later matches
+/// are drawn as plain directories when something sampled lies below them, and dropped otherwise.
+fn sample(node: &mut Tree, depth: usize, limit: usize, seen: &mut Vec<usize>) {
+    if seen.len() <= depth {
+        seen.resize(depth + 1, 0);
+    }
+    node.children.retain_mut(|child| {
+        if child.matched {
+            if seen[depth] < limit {
+                seen[depth] += 1;
+            } else {
+                child.matched = false;
+            }
+        }
+        sample(child, depth + 1, limit, seen);
+        // A score of 0 is a `--show-empty` directory, kept for context.
+        child.matched || child.score == 0 || child.collapsed.is_some() || !child.children.is_empty()
+    });
+}
+
+/// Formats a byte count with a binary unit, like `du -h`: `512 B`, `1.5 KiB`, `12.0 MiB`.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Resolves an `--owner` (or, with `group`, `--group`) value to an id: numbers are taken as
+/// ids, anything else is looked up in the user or group database (`/etc/passwd`, `/etc/group`).
+#[cfg(unix)]
+fn resolve_id(spec: &str, group: bool) -> Option<u32> {
+    spec.parse().ok().or_else(|| {
+        if group {
+            users::get_group_by_name(spec).map(|group| group.gid())
+        } else {
+            users::get_user_by_name(spec).map(|user| user.uid())
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn resolve_id(_spec: &str, _group: bool) -> Option<u32> {
+    None
+}
+
+/// Parses the value of `--flag` as a number, above zero when `positive` is set, exiting with an
+/// error that names the flag otherwise.
+fn number_arg<T: FromStr + Default + PartialOrd>(flag: &str, value: &str, positive: bool) -> T {
+    match value.parse::<T>() {
+        Ok(number) if !positive || number > T::default() => number,
+        _ => {
+            let expected = if positive { "a positive number" } else { "a number" };
+            eprintln!("xtree: --{} expects {}, got {}", flag, expected, value);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// Parses a size such as `10M`, `1.5GiB`, `500KB` or `4096`: `K`, `M`, `G` and `T` (with an
+/// optional `iB`) are powers of 1024, while `KB`, `MB`, `GB` and `TB` are powers of 1000, as in
+/// `du`. Case is ignored and a bare number is in bytes.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let amount: f64 = value[..split].parse().ok()?;
+    let (base, exp): (f64, i32) = match value[split..].trim().to_ascii_lowercase().as_str() {
+        "" | "b" => (1.0, 0),
+        "k" | "kib" => (1024.0, 1),
+        "m" | "mib" => (1024.0, 2),
+        "g" | "gib" => (1024.0, 3),
+        "t" | "tib" => (1024.0, 4),
+        "kb" => (1000.0, 1),
+        "mb" => (1000.0, 2),
+        "gb" => (1000.0, 3),
+        "tb" => (1000.0, 4),
+        _ => return None,
+    };
+    let bytes = amount * base.powi(exp);
+    (bytes.is_finite() && bytes <= u64::MAX as f64).then_some(bytes as u64)
+}
+
+/// Parses a `--highlight-bg` color: one of the eight ANSI color names, optionally prefixed with
+/// `bright-`, or its background code (40–47, 100–107).
+fn parse_background(name: &str) -> Option<u8> {
+    const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    let name = name.to_lowercase();
+    if let Ok(code) = name.parse() {
+        return matches!(code, 40..=47 | 100..=107).then_some(code);
+    }
+    let (base, plain) = match name.strip_prefix("bright-") {
+        Some(plain) => (100, plain),
+        None => (40, name.as_str()),
+    };
+    NAMES.iter().position(|&known| known == plain).map(|index| base + index as u8)
+}
+
+/// Highlights the search terms in `name`, in NFC form when normalizing.
+fn highlight(name: &str, popts: &PrintOptions) -> String {
+    if popts.normalize {
+        highlight_styled(&nfc(name), popts.terms_lower, &popts.highlight)
+    } else {
+        highlight_styled(name, popts.terms_lower, &popts.highlight)
     }
 }
 
-/// Highlights the first occurrence of `substr` in `s` with ANSI red color.
-/// Assumes ASCII so that byte indices match character boundaries.
-fn highlight_substring(s: &str, substr_lower: &str) -> String {
-    let s_lower = s.to_lowercase();
-    if let Some(pos) = s_lower.find(substr_lower) {
-        let before = &s[..pos];
-        let matched = &s[pos..pos + substr_lower.len()];
-        let after = &s[pos + substr_lower.len()..];
-        format!("{}{}{}{}{}", before, "\x1b[91m", matched, "\x1b[0m", after)
+/// Highlights the search terms within the basename of a root path, leaving its parent
+/// components untouched.
+fn highlight_root(name: &str, popts: &PrintOptions) -> String {
+    let name = if popts.normalize { nfc(name) } else { name.to_string() };
+    let start = Path::new(&name)
+        .file_name()
+        .and_then(|base| name.rfind(base.to_string_lossy().as_ref()))
+        .unwrap_or(0);
+    format!(
+        "{}{}",
+        &name[..start],
+        highlight_styled(&name[start..], popts.terms_lower, &popts.highlight)
+    )
+}
+
+/// Styles a node's name: matches are highlighted and, under `--show-empty`, directories without
+/// matches below are dimmed.
+fn display_name(node: &Tree, popts: &PrintOptions) -> String {
+    let name = if node.matched && popts.color {
+        // A chain folded by --summary-tree is highlighted in its last segment, the match.
+        if node.name.contains('/') {
+            highlight_root(&node.name, popts)
+        } else {
+            highlight(&node.name, popts)
+        }
+    } else if popts.color && node.score == 0 {
+        format!("\x1b[2m{}\x1b[0m", node.name)
     } else {
-        s.to_owned()
+        node.name.clone()
+    };
+    let name = match popts.ls_colors.as_ref().and_then(|colors| colors.lookup(node)) {
+        // Re-apply the file's color after every highlight ends.
+        Some(sgr) if popts.color => {
+            let base = format!("\x1b[{}m", sgr);
+            format!("{}{}\x1b[0m", base, name.replace("\x1b[0m", &format!("\x1b[0m{}", base)))
+        }
+        _ => name,
+    };
+    match popts.replace.filter(|_| node.matched) {
+        Some((from_lower, to)) => match replace_ignoring_case(&node.name, from_lower, to) {
+            Some(renamed) => format!("{} → {}", name, renamed),
+            None => name,
+        },
+        None => name,
+    }
+}
+
+/// File colors from `LS_COLORS`, in `dircolors` format (`*.rs=01;31:ln=36:...`).
+struct LsColors {
+    /// `(suffix, SGR)` pairs from `*suffix=SGR` entries, longest suffix first.
+    suffixes: Vec<(String, String)>,
+    /// `fi=`: regular files without a suffix color.
+    file: Option<String>,
+    /// `ln=`: symbolic links.
+    link: Option<String>,
+}
+
+impl LsColors {
+    fn parse(spec: &str) -> LsColors {
+        let mut colors = LsColors {
+            suffixes: Vec::new(),
+            file: None,
+            link: None,
+        };
+        for (key, sgr) in spec.split(':').filter_map(|entry| entry.split_once('=')) {
+            // Only plain SGR parameters can be emitted safely.
+            if sgr.is_empty() || !sgr.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                continue;
+            }
+            match key {
+                "fi" => colors.file = Some(sgr.to_string()),
+                "ln" if sgr != "target" => colors.link = Some(sgr.to_string()),
+                _ => {
+                    if let Some(suffix) = key.strip_prefix('*') {
+                        colors.suffixes.push((suffix.to_lowercase(), sgr.to_string()));
+                    }
+                }
+            }
+        }
+        colors.suffixes.sort_by_key(|(suffix, _)| Reverse(suffix.len()));
+        colors
+    }
+
+    /// The SGR parameters for `node`; directories are never colored.
+    fn lookup(&self, node: &Tree) -> Option<&str> {
+        match node.kind {
+            EntryKind::Dir => None,
+            EntryKind::Symlink => self.link.as_deref(),
+            EntryKind::File => {
+                let name = node.name.to_lowercase();
+                self.suffixes
+                    .iter()
+                    .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+                    .map(|(_, sgr)| sgr.as_str())
+                    .or(self.file.as_deref())
+            }
+        }
+    }
+}
+
+/// Replaces every case-insensitive occurrence of `from_lower` in `name` with `to`, returning
+/// `None` when there is none.
+fn replace_ignoring_case(name: &str, from_lower: &str, to: &str) -> Option<String> {
+    if from_lower.is_empty() {
+        return None;
+    }
+    let mut out = String::with_capacity(name.len());
+    let mut rest = name;
+    let mut replaced = false;
+    while let Some(c) = rest.chars().next() {
+        // The shortest prefix of `rest` whose lower-case form is `from_lower`, if any.
+        let found = rest
+            .char_indices()
+            .map(|(i, c)| i + c.len_utf8())
+            .map(|end| (end, rest[..end].to_lowercase()))
+            .take_while(|(_, lower)| from_lower.starts_with(lower.as_str()))
+            .find(|(_, lower)| lower == from_lower);
+        match found {
+            Some((end, _)) => {
+                out.push_str(to);
+                rest = &rest[end..];
+                replaced = true;
+            }
+            None => {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    replaced.then_some(out)
+}
+
+/// Collects `(depth, old path, new path)` for every match below `node` that `--replace` renames.
+fn collect_renames(
+    node: &Tree,
+    path: &Path,
+    depth: usize,
+    replace: (&str, &str),
+    out: &mut Vec<(usize, PathBuf, PathBuf)>,
+) {
+    for child in &node.children {
+        let child_path = path.join(&child.name);
+        if child.matched {
+            if let Some(renamed) = replace_ignoring_case(&child.name, replace.0, replace.1) {
+                out.push((depth + 1, child_path.clone(), path.join(renamed)));
+            }
+        }
+        collect_renames(child, &child_path, depth + 1, replace, out);
+    }
+}
+
+/// Performs the renames previewed by `--replace`, deepest first so that no rename invalidates a
+/// path still to be renamed. Nothing is renamed if any target already exists or two entries would
+/// get the same name. Returns the number of entries renamed.
+fn apply_renames(tree: &Tree, replace: (&str, &str)) -> Result<usize, String> {
+    let mut renames = Vec::new();
+    collect_renames(tree, Path::new(&tree.name), 0, replace, &mut renames);
+
+    let mut targets = HashSet::new();
+    for (_, old, new) in &renames {
+        if !targets.insert(new) {
+            return Err(format!("more than one entry would become '{}'; nothing was renamed", new.display()));
+        }
+        // A case-only rename finds its own source on case-insensitive filesystems.
+        let case_only = old.to_string_lossy().to_lowercase() == new.to_string_lossy().to_lowercase();
+        if !case_only && fs::symlink_metadata(new).is_ok() {
+            return Err(format!("'{}' already exists; nothing was renamed", new.display()));
+        }
+    }
+
+    renames.sort_by_key(|(depth, _, _)| Reverse(*depth));
+    for (_, old, new) in &renames {
+        fs::rename(old, new).map_err(|err| format!("cannot rename '{}': {}", old.display(), err))?;
+    }
+    Ok(renames.len())
+}
+
+/// Prints the tree level by level: every depth-1 directory, then every depth-2 one, and so on.
+/// Connectors cannot express this order, so each line shows its path relative to the root,
+/// indented two spaces per level.
+fn print_tree_breadth_first(tree: &Tree, popts: &PrintOptions) {
+    let mut queue: VecDeque<(&Tree, String, usize)> =
+        tree.children.iter().map(|child| (child, String::new(), 1)).collect();
+
+    while let Some((node, parent, depth)) = queue.pop_front() {
+        if popts.display_depth.is_some_and(|max| depth > max) {
+            continue;
+        }
+        let name = hyperlink(
+            display_name(node, popts),
+            &Path::new(&tree.name).join(&parent).join(&node.name),
+            popts,
+        );
+        let line = format!("{}{}{}", "  ".repeat(depth), parent, annotate(name, node));
+        println!("{}", fit_width(line, popts.max_width));
+        let path = format!("{}{}/", parent, node.name);
+        queue.extend(node.children.iter().map(|child| (child, path.clone(), depth + 1)));
+    }
+}
+
+/// Collects the paths of matching nodes that have no matching descendants, in tree order.
+fn collect_tips<'t>(node: &'t Tree, path: &Path, tips: &mut Vec<(PathBuf, &'t Tree)>) {
+    if node.matched && node.count_matches() == 1 {
+        tips.push((path.to_path_buf(), node));
+        return;
+    }
+    for child in &node.children {
+        collect_tips(child, &path.join(&child.name), tips);
+    }
+}
+
+/// Prints the tips of the tree (see [`collect_tips`]) one full path per line, with the match
+/// highlighted in the last component.
+fn print_tips(tree: &Tree, popts: &PrintOptions) {
+    let mut tips = Vec::new();
+    collect_tips(tree, Path::new(&tree.name), &mut tips);
+    for (path, node) in tips {
+        let shown = relative_path(&path, popts.relative_to.as_deref()).display().to_string();
+        let line = if popts.color {
+            highlight_root(&shown, popts)
+        } else {
+            shown
+        };
+        let line = hyperlink(line, &path, popts);
+        println!("{}", fit_width(annotate(line, node), popts.max_width));
     }
 }
 
 /// Recursively prints the tree structure with branch lines.
 ///
 /// - `skip_first`: if true, the current level isn’t printed (used for the root).
-/// - `count`: if false (i.e. at the top‐level call), the total matching directories count is printed.
-fn print_tree(tree: &Tree, searchterm_lower: &str, prefix: &str, skip_first: bool, count: bool) -> u32 {
-    let mut dir_count = 0;
-    let num_children = tree.children.len();
-
-    for (i, child) in tree.children.iter().enumerate() {
-        let is_last = i == num_children - 1;
-        let branch = if is_last { "└── " } else { "├── " };
+/// - `depth`: the depth of `tree`'s children; below `popts.display_depth` they are not drawn.
+/// - `path`: where `tree` is, for hyperlinks.
+/// - `anchor`: the depth of the shallowest match in the current top-level branch, which
+///   `--depth-colors relative` counts levels from.
+///
+/// Matching files and symlinks beyond `popts.max_matches_per_dir` in one directory are replaced
+/// by a single `... (K more matches)` line. The footer is left to the caller (see
+/// [`Count::tally`]).
+#[allow(clippy::too_many_arguments)]
+fn print_tree(
+    out: &mut impl Write,
+    tree: &Tree,
+    popts: &PrintOptions,
+    prefix: &str,
+    skip_first: bool,
+    depth: usize,
+    path: &Path,
+    anchor: Option<usize>,
+) -> io::Result<()> {
+    if popts.display_depth.is_some_and(|max| depth > max) {
+        return Ok(());
+    }
+
+    let mut leaf_matches = 0;
+    let (shown, capped): (Vec<&Tree>, Vec<&Tree>) = tree.children.iter().partition(|child| {
+        if child.kind == EntryKind::Dir || !child.matched {
+            return true;
+        }
+        leaf_matches += 1;
+        popts.max_matches_per_dir.is_none_or(|max| leaf_matches <= max)
+    });
+    let num_children = shown.len();
+    let (corner, tee, pipe) = connectors(popts);
+    for (i, child) in shown.into_iter().enumerate() {
+        let child_path = path.join(&child.name);
+        let is_last = i == num_children - 1 && capped.is_empty();
+        let branch = if popts.indent_only {
+            "  "
+        } else if is_last {
+            corner
+        } else {
+            tee
+        };
         let next_prefix = if skip_first {
             prefix.to_string()
+        } else if popts.indent_only {
+            format!("{}  ", prefix)
         } else if is_last {
             format!("{}    ", prefix)
+        } else if popts.rtl {
+            format!("{}{}", pipe, prefix)
         } else {
-            format!("{}│   ", prefix)
+            format!("{}{}", prefix, pipe)
         };
 
+        let anchor = if depth == 1 { shallowest_match(child, depth) } else { anchor };
         if !skip_first {
-            let child_name_lower = child.name.to_lowercase();
-            let display_name = if child_name_lower.contains(searchterm_lower) {
-                dir_count += 1;
-                highlight_substring(&child.name, searchterm_lower)
-            } else {
-                child.name.clone()
+            let branch = match popts.depth_colors {
+                Some(mode) => depth_colored(branch, depth, if mode == "relative" { anchor } else { Some(1) }),
+                None => branch.to_string(),
             };
-            println!("{}{}{}", prefix, branch, display_name);
+            let line = Line {
+                name: hyperlink(display_name(child, popts), &child_path, popts),
+                node: child,
+                path: &child_path,
+                depth,
+                prefix,
+                branch: &branch,
+            };
+            writeln!(out, "{}", fit_width(format_line(line, popts), popts.max_width))?;
         }
 
         let child_prefix = if skip_first { prefix.to_string() } else { next_prefix };
-        dir_count += print_tree(child, searchterm_lower, &child_prefix, false, true);
+        print_tree(out, child, popts, &child_prefix, false, depth + 1, &child_path, anchor)?;
+    }
+    if !capped.is_empty() {
+        let more: u32 = capped.iter().map(|child| child.count_matches()).sum();
+        let branch = if popts.indent_only { "  " } else { corner };
+        let note = format!("... ({} more {})", more, if more == 1 { "match" } else { "matches" });
+        if popts.rtl {
+            writeln!(out, "{}{}{}", note, branch, prefix)?;
+        } else {
+            writeln!(out, "{}{}{}", prefix, branch, note)?;
+        }
     }
+    Ok(())
+}
 
-    if !count {
-        println!(
-            "\n{} {}",
-            dir_count,
-            if dir_count == 1 { "directory" } else { "directories" }
-        );
+/// Colors of the connectors under `--depth-colors`, one per level, repeating.
+const DEPTH_COLORS: [&str; 5] = ["\x1b[36m", "\x1b[34m", "\x1b[35m", "\x1b[33m", "\x1b[32m"];
+
+/// Colors the connector `branch` of an entry at `depth` by its level counted from `anchor`, the
+/// depth that gets the first color; levels above it are dimmed, as context leading to a match.
+fn depth_colored(branch: &str, depth: usize, anchor: Option<usize>) -> String {
+    match anchor.and_then(|anchor| depth.checked_sub(anchor)) {
+        Some(level) => format!("{}{}\x1b[0m", DEPTH_COLORS[level % DEPTH_COLORS.len()], branch),
+        None => format!("\x1b[2m{}\x1b[0m", branch),
     }
-    dir_count
 }
 
-fn main() {
-    // Clone the app so that we can later print the help message.
-    let mut app = App::new("xtree")
-        .version("0.1")
-        .about("A lightweight directory tree generator.")
-        .arg(
-            Arg::new("search")
-                .help("Search term to filter directory names (if provided)")
-                .required(false)
-                .index(1),
-        )
-        .arg(
-            Arg::new("directory")
-                .help("Directory to generate tree from (default: current directory)")
-                .required(false)
-                .index(2),
-        )
-        .arg(
-            Arg::new("depth")
-                .short('d')
-                .long("depth")
-                .help("Maximum depth of directory tree (default: 3)")
-                .takes_value(true)
-                .default_value("3"),
-        );
+/// Returns the depth of the shallowest match in the subtree of `node`, found at `depth`.
+fn shallowest_match(node: &Tree, depth: usize) -> Option<usize> {
+    if node.matched {
+        return Some(depth);
+    }
+    node.children.iter().filter_map(|child| shallowest_match(child, depth + 1)).min()
+}
+
+/// Returns the connectors drawn before the last entry of a directory, before its other entries,
+/// and below those other entries: box-drawing characters, or plain ASCII with `--ascii`.
+/// With `--rtl`, they are mirrored, to be drawn after the names.
+fn connectors(popts: &PrintOptions) -> (&'static str, &'static str, &'static str) {
+    match (popts.ascii, popts.rtl) {
+        (true, false) => ("`-- ", "|-- ", "|   "),
+        (true, true) => (" --'", " --|", "   |"),
+        (false, false) => ("└── ", "├── ", "│   "),
+        (false, true) => (" ──┘", " ──┤", "   │"),
+    }
+}
 
-    let matches = app.clone().get_matches();
+/// First strong isolate and pop directional isolate: around each name in `--rtl` trees.
+const FSI: char = '\u{2068}';
+const PDI: char = '\u{2069}';
 
-    let search = matches.value_of("search").unwrap_or("");
-    if search.is_empty() {
-        app.print_help().expect("Failed to print help");
-        println!();
+/// Pads `lines` on the left so they all end in the same column, as a `--rtl` tree is drawn.
+/// Escape sequences and the isolates around names take no width.
+fn align_right<'a>(lines: impl IntoIterator<Item = &'a str>) -> String {
+    let lines: Vec<(&str, usize)> = lines
+        .into_iter()
+        .map(|line| {
+            let visible = ansi_pieces(line).filter(|&(escape, piece)| !escape && !piece.starts_with([FSI, PDI]));
+            (line, visible.count())
+        })
+        .collect();
+    let width = lines.iter().map(|&(_, width)| width).max().unwrap_or(0);
+    let mut out = String::new();
+    for (line, visible) in lines {
+        out.push_str(&" ".repeat(width - visible));
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Adds the matches of `node` (at `depth`) and its subtree to `counts`, indexed by depth.
+fn tally_depths(node: &Tree, depth: usize, counts: &mut Vec<u32>) {
+    if node.matched {
+        if counts.len() <= depth {
+            counts.resize(depth + 1, 0);
+        }
+        counts[depth] += 1;
+    }
+    for child in &node.children {
+        tally_depths(child, depth + 1, counts);
+    }
+}
+
+/// Prints one bar per depth showing how many matches it holds, scaled so the longest bar fits
+/// the terminal (`COLUMNS`, or 80). Bars are drawn with `#` when colors are off.
+fn print_histogram(tree: &Tree, popts: &PrintOptions) {
+    let mut counts = Vec::new();
+    tally_depths(tree, 0, &mut counts);
+    let Some(&most) = counts.iter().max() else {
         return;
+    };
+    let columns: usize = env::var("COLUMNS").ok().and_then(|cols| cols.parse().ok()).unwrap_or(80);
+    let label_width = format!("d{}: ", counts.len() - 1).len() + format!(" {}", most).len();
+    let room = columns.saturating_sub(label_width).max(1);
+    let bar = if popts.color { "█" } else { "#" };
+    println!();
+    for (depth, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let len = (count as usize * room).div_ceil(most as usize);
+        println!("d{}: {} {}", depth, bar.repeat(len), count);
     }
+}
 
-    let directory = matches.value_of("directory").unwrap_or(".");
-    let depth: usize = matches
-        .value_of("depth")
-        .unwrap_or("3")
-        .parse()
-        .unwrap_or(3);
-    // Precompute the lower-case version of the search term.
-    let search_lower = search.to_lowercase();
+/// Prints a key explaining each color the tree may use. Nothing is printed without colors.
+fn print_legend(popts: &PrintOptions) {
+    if !popts.color {
+        return;
+    }
+    let mut entries: Vec<String> = popts
+        .terms_lower
+        .iter()
+        .enumerate()
+        .map(|(i, term)| {
+            let (_, name) = HIGHLIGHT_COLORS[i % HIGHLIGHT_COLORS.len()];
+            format!("{}{}\x1b[0m = term '{}'", highlight_sgr(i, popts.highlight.background), name, term)
+        })
+        .collect();
+    if popts.show_empty {
+        entries.push("\x1b[2mdim\x1b[0m = no matches below".to_string());
+    }
+    if !entries.is_empty() {
+        println!("Legend: {}", entries.join(", "));
+    }
+}
 
-    if let Some(tree) = build_tree_dict(directory, &search_lower, depth) {
-        // Print the root directory.
-        println!("{}", tree.name);
-        // Print the rest of the tree.
-        print_tree(&tree, &search_lower, "", false, false);
-    } else {
-        println!("No directories match the search term.");
+/// The document written by `--stats-json`. Fields may be added but are never renamed or removed.
+#[derive(Serialize)]
+struct Stats {
+    /// Bumped if a field ever changes meaning.
+    version: u32,
+    /// Matched entries, as `--count all` tallies them.
+    matches: u32,
+    /// Directories listed, including the skipped ones.
+    scanned: usize,
+    /// Directories that could not be read.
+    skipped: usize,
+    /// Deepest level any entry was found at.
+    max_depth: usize,
+    /// Whether `--depth` stopped the scan before the bottom of the tree.
+    depth_capped: bool,
+    /// Whether `--scan-budget` ran out before the scan was done.
+    over_budget: bool,
+    /// Time spent scanning, not printing.
+    elapsed_ms: u128,
+    /// Matches at each depth, the root being depth 0.
+    matches_by_depth: Vec<u32>,
+}
+
+/// Writes the statistics of a finished scan to `file` (`--stats-json`), replacing it.
+fn write_stats(file: &str, tree: Option<&Tree>, report: &ScanReport, elapsed: Duration) -> io::Result<()> {
+    let mut matches_by_depth = Vec::new();
+    if let Some(tree) = tree {
+        tally_depths(tree, 0, &mut matches_by_depth);
     }
+    let stats = Stats {
+        version: 1,
+        matches: tree.map_or(0, Tree::count_matches),
+        scanned: report.scanned,
+        skipped: report.skipped.len(),
+        max_depth: report.deepest,
+        depth_capped: report.capped,
+        over_budget: report.over_budget,
+        elapsed_ms: elapsed.as_millis(),
+        matches_by_depth,
+    };
+    fs::write(file, serde_json::to_vec_pretty(&stats)?)
+}
+
+/// The document printed by `--format json` and `--format yaml`.
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    /// Number of matching directories in `tree`.
+    matches: u32,
+    /// The filtered tree, or `null` when nothing matched.
+    tree: Option<&'a Tree>,
 }
+
+/// Prints the tree as a JSON document, compact on one line unless `pretty` is set.
+fn print_json(tree: Option<&Tree>, pretty: bool) -> io::Result<()> {
+    let report = JsonReport {
+        matches: tree.map_or(0, Tree::count_matches),
+        tree,
+    };
+    let mut out = io::stdout().lock();
+    if pretty {
+        serde_json::to_writer_pretty(&mut out, &report)?;
+    } else {
+        serde_json::to_writer(&mut out, &report)?;
+    }
+    writeln!(out)
+}
+
+/// Prints the tree as a YAML document with the same fields as `--format json`.
+fn print_yaml(tree: Option<&Tree>) -> io::Result<()> {
+    let report = JsonReport {
+        matches: tree.map_or(0, Tree::count_matches),
+        tree,
+    };
+    serde_yaml::to_writer(io::stdout().lock(), &report).map_err(io::Error::other)
+}
+
+/// Connects to the `--socket` address: `HOST:PORT` (an IP address, or any host name after
+/// `tcp:`) over TCP, anything else as the path of a Unix domain socket.
+fn connect_socket(addr: &str) -> io::Result<BufWriter<Box<dyn Write>>> {
+    let stream: Box<dyn Write> = if let Some(host) = addr.strip_prefix("tcp:") {
+        Box::new(TcpStream::connect(host)?)
+    } else if let Ok(ip) = addr.parse::<SocketAddr>() {
+        Box::new(TcpStream::connect(ip)?)
+    } else {
+        connect_unix(addr)?
+    };
+    Ok(BufWriter::new(stream))
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> io::Result<Box<dyn Write>> {
+    Ok(Box::new(UnixStream::connect(path)?))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_path: &str) -> io::Result<Box<dyn Write>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no Unix domain sockets on this platform; use HOST:PORT"))
+}
+
+/// Prints the tree as an org-mode outline (`--format org`): one headline per node, with as many
+/// stars as its depth plus one, and matched names set in `=verbatim=`.
+fn print_org(out: &mut impl Write, node: &Tree, depth: usize) -> io::Result<()> {
+    let name = if node.matched { format!("={}=", node.name) } else { node.name.clone() };
+    writeln!(out, "{} {}", "*".repeat(depth + 1), name)?;
+    for child in &node.children {
+        print_org(out, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Colors of the highlighted terms in `--format html`, in the order of [`HIGHLIGHT_COLORS`],
+/// darkened to read on a white page.
+const HTML_TERM_COLORS: [&str; 6] = ["#c00", "#080", "#a60", "#00c", "#a0a", "#088"];
+
+/// Stylesheet of `--format html`; the term colors and `--highlight-bg` are added after it.
+const HTML_STYLE: &str = "body { font-family: monospace; }
+ul { list-style: none; margin: 0; padding-left: 1.5em; }
+ul.tree { padding-left: 0; }
+li.dir > .name { cursor: pointer; }
+li.dir > .name::before { content: \"\\25BE  \"; }
+li.dir.collapsed > .name::before { content: \"\\25B8  \"; }
+li.collapsed > ul { display: none; }
+li:not(.match) > .name { color: #666; }
+.match > .name { font-weight: bold; }
+";
+
+/// Script of `--format html`: clicking a directory's name collapses or expands its entries.
+const HTML_SCRIPT: &str = "document.querySelectorAll(\"li.dir > .name\").forEach(function (name) {
+  name.addEventListener(\"click\", function () { name.parentElement.classList.toggle(\"collapsed\"); });
+});
+";
+
+/// Escapes the characters of `s` that HTML gives a meaning to.
+fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Returns the CSS color of a `--highlight-bg` code (40–47, or 100–107 for the bright ones).
+fn css_background(code: u8) -> &'static str {
+    const PLAIN: [&str; 8] = ["black", "maroon", "green", "olive", "navy", "purple", "teal", "silver"];
+    const BRIGHT: [&str; 8] = ["gray", "red", "lime", "yellow", "blue", "fuchsia", "aqua", "white"];
+    match code {
+        100..=107 => BRIGHT[usize::from(code - 100)],
+        _ => PLAIN[usize::from(code.saturating_sub(40)).min(7)],
+    }
+}
+
+/// Prints the tree as a standalone HTML page (`--format html`): a nested `<ul>` whose
+/// directories collapse when their name is clicked, with each term highlighted by a
+/// `<span class="hl tN">` and the footer below.
+fn print_html(out: &mut impl Write, tree: &Tree, popts: &PrintOptions) -> io::Result<()> {
+    writeln!(out, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>xtree: {}</title>\n<style>", html_escape(&tree.name))?;
+    write!(out, "{}", HTML_STYLE)?;
+    for (i, color) in HTML_TERM_COLORS.iter().enumerate() {
+        writeln!(out, ".t{} {{ color: {}; }}", i, color)?;
+    }
+    if let Some(background) = popts.highlight.background {
+        writeln!(out, ".hl {{ background-color: {}; }}", css_background(background))?;
+    }
+    writeln!(out, "</style>\n</head>\n<body>\n<ul class=\"tree\">")?;
+    print_html_node(out, tree, popts)?;
+    writeln!(out, "</ul>")?;
+    if popts.summary {
+        let summary = format_summary(popts.summary_format, popts.count.tally(tree));
+        writeln!(out, "<p class=\"summary\">{}</p>", html_escape(&summary))?;
+    }
+    writeln!(out, "<script>\n{}</script>\n</body>\n</html>", HTML_SCRIPT)
+}
+
+/// Writes `node` and its subtree as one `<li>` of `--format html`.
+fn print_html_node(out: &mut impl Write, node: &Tree, popts: &PrintOptions) -> io::Result<()> {
+    let name = if popts.normalize { nfc(&node.name) } else { node.name.clone() };
+    let mut shown = String::new();
+    // Only the last component is matched, in the root's path or a `--summary-tree` chain.
+    let base = name.rfind('/').map_or(0, |slash| slash + 1);
+    let mut last = 0;
+    if node.matched {
+        for span in highlight_spans(&name[base..], popts.terms_lower, &popts.highlight) {
+            let (start, end) = (base + span.start, base + span.end);
+            shown.push_str(&html_escape(&name[last..start]));
+            let term = span.term % HTML_TERM_COLORS.len();
+            shown.push_str(&format!("<span class=\"hl t{}\">{}</span>", term, html_escape(&name[start..end])));
+            last = end;
+        }
+    }
+    shown.push_str(&html_escape(&name[last..]));
+    let kind = match node.kind {
+        EntryKind::Dir => "dir",
+        EntryKind::File => "file",
+        EntryKind::Symlink => "symlink",
+    };
+    let class = if node.matched { format!("{} match", kind) } else { kind.to_string() };
+    write!(out, "<li class=\"{}\"><span class=\"name\">{}</span>", class, shown)?;
+    let notes = annotate(String::new(), node);
+    if !notes.is_empty() {
+        write!(out, "<span class=\"notes\">{}</span>", html_escape(&notes))?;
+    }
+    if node.children.is_empty() {
+        return writeln!(out, "</li>");
+    }
+    writeln!(out, "<ul>")?;
+    for child in &node.children {
+        print_html_node(out, child, popts)?;
+    }
+    writeln!(out, "</ul></li>")
+}
+
+/// One line of `--format ndjson`.
+#[derive(Serialize)]
+struct NdjsonRecord {
+    id: String,
+    path: String,
+    kind: EntryKind,
+    depth: usize,
+    score: u32,
+}
+
+/// One entry of `--format matches-json`: where a term was found in a match's name.
+#[derive(Serialize)]
+struct MatchSpan<'a> {
+    path: String,
+    /// Byte offsets of the highlighted span within the basename.
+    match_start: usize,
+    match_end: usize,
+    term: &'a str,
+}
+
+/// Prints every span of every match under `directory` that the tree would highlight as one JSON
+/// array, for editors that draw their own highlighting. Matches with nothing to highlight (found
+/// by a filter alone) have no entry. Returns whether anything matched.
+fn print_match_spans(directory: &str, opts: &ScanOptions, popts: &PrintOptions) -> io::Result<bool> {
+    let mut any = false;
+    let mut spans = Vec::new();
+    let mut walker = matches(Path::new(directory), opts);
+    for found in walker.by_ref() {
+        any = true;
+        let name = found.path.file_name().unwrap_or(found.path.as_os_str()).to_string_lossy();
+        let path = relative_path(&found.path, popts.relative_to.as_deref()).to_string_lossy().into_owned();
+        spans.extend(highlight_spans(&name, popts.terms_lower, &popts.highlight).into_iter().map(|span| MatchSpan {
+            path: path.clone(),
+            match_start: span.start,
+            match_end: span.end,
+            term: &popts.terms_lower[span.term],
+        }));
+    }
+    if let Some(err) = walker.take_error() {
+        fail_scan(err);
+    }
+    let mut out = io::stdout().lock();
+    if popts.json_pretty {
+        serde_json::to_writer_pretty(&mut out, &spans)?;
+    } else {
+        serde_json::to_writer(&mut out, &spans)?;
+    }
+    writeln!(out)?;
+    Ok(any)
+}
+
+/// Prints every match under `directory` as one line, most relevant first: by the number of
+/// distinct terms its name contains, then by how few edits `--max-distance` needed, then by how
+/// often they occur, then by path. Each line ends with the terms found (`[foo, test ×2]`),
+/// unless printed `--grep` style. Returns whether anything matched.
+fn print_ranked(directory: &str, opts: &ScanOptions, popts: &PrintOptions) -> bool {
+    let mut walker = matches(Path::new(directory), opts);
+    let mut counted = 0;
+    let mut ranked: Vec<_> = walker
+        .by_ref()
+        .map(|found| {
+            counted += u32::from(popts.count.includes(found.kind));
+            let hits = opts.term_hits(&found.path, found.depth);
+            let edits = opts.edit_distance(&found.path, found.depth);
+            (found.path, hits, edits)
+        })
+        .collect();
+    if let Some(err) = walker.take_error() {
+        fail_scan(err);
+    }
+    ranked.sort_by_cached_key(|(path, hits, edits)| {
+        let occurrences: usize = hits.iter().map(|&(_, count)| count).sum();
+        (Reverse(hits.len()), *edits, Reverse(occurrences), path.clone())
+    });
+    for (path, hits, _) in &ranked {
+        if popts.grep || popts.breadcrumbs.is_some() {
+            println!("{}", match_line(path, popts));
+            continue;
+        }
+        let shown = relative_path(path, popts.relative_to.as_deref()).display().to_string();
+        let mut line = hyperlink(if popts.color { highlight_root(&shown, popts) } else { shown }, path, popts);
+        if !hits.is_empty() {
+            let terms: Vec<String> = hits
+                .iter()
+                .map(|&(index, count)| match count {
+                    1 => popts.terms_lower[index].clone(),
+                    _ => format!("{} ×{}", popts.terms_lower[index], count),
+                })
+                .collect();
+            line = format!("{} [{}]", line, terms.join(", "));
+        }
+        println!("{}", fit_width(line, popts.max_width));
+    }
+    if popts.summary && !popts.grep && popts.breadcrumbs.is_none() && !ranked.is_empty() {
+        println!("\n{}", format_summary(popts.summary_format, counted));
+    }
+    !ranked.is_empty()
+}
+
+/// Formats a match the way `--grep` prints it: its whole path, in magenta like grep's file names,
+/// with the matched part of its name highlighted.
+fn grep_line(path: &Path, popts: &PrintOptions) -> String {
+    let shown = relative_path(path, popts.relative_to.as_deref()).display().to_string();
+    let line = if popts.color {
+        let highlighted = highlight_root(&shown, popts);
+        format!("\x1b[35m{}\x1b[0m", highlighted.replace("\x1b[0m", "\x1b[0m\x1b[35m"))
+    } else {
+        shown
+    };
+    fit_width(hyperlink(line, path, popts), popts.max_width)
+}
+
+/// Formats a match the way `--breadcrumbs` prints it: the components of its path joined by
+/// `separator`, which is dimmed, with the matched part of its name highlighted.
+fn breadcrumb_line(path: &Path, separator: &str, popts: &PrintOptions) -> String {
+    let shown = relative_path(path, popts.relative_to.as_deref());
+    let mut crumbs: Vec<String> = shown
+        .components()
+        .filter(|component| !matches!(component, Component::RootDir))
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if let Some(name) = crumbs.last_mut().filter(|_| popts.color) {
+        *name = highlight_root(name, popts);
+    }
+    let joint = if popts.color {
+        format!(" \x1b[2m{}\x1b[0m ", separator)
+    } else {
+        format!(" {} ", separator)
+    };
+    fit_width(hyperlink(crumbs.join(&joint), path, popts), popts.max_width)
+}
+
+/// Formats a match as `--breadcrumbs` or `--grep` prints it, whichever is on.
+fn match_line(path: &Path, popts: &PrintOptions) -> String {
+    match popts.breadcrumbs {
+        Some(separator) => breadcrumb_line(path, separator, popts),
+        None => grep_line(path, popts),
+    }
+}
+
+/// Prints every match under `directory` as one `--grep` (or `--breadcrumbs`) line, as the scan
+/// finds them, with no footer. Returns whether anything matched.
+fn print_grep(directory: &str, opts: &ScanOptions, popts: &PrintOptions) -> bool {
+    let mut walker = matches(Path::new(directory), opts);
+    let mut out = LineFlusher::new(io::stdout().lock());
+    let mut found = false;
+    let written = walker
+        .by_ref()
+        .try_for_each(|entry| {
+            found = true;
+            writeln!(out, "{}", match_line(&entry.path, popts))
+        })
+        .and_then(|_| out.flush());
+    match written {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return true,
+        Err(err) => {
+            eprintln!("xtree: cannot write the matches: {}", err);
+            process::exit(EXIT_ERROR);
+        }
+    }
+    if let Some(err) = walker.take_error() {
+        fail_scan(err);
+    }
+    found
+}
+
+/// Formats an age with the largest unit of `--filter`'s `mtime` that fits: `45s`, `12m`, `3h`,
+/// `5d`, `2w`.
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (unit, size) = [("w", 7 * 24 * 60 * 60), ("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)]
+        .into_iter()
+        .find(|&(_, size)| secs >= size)
+        .unwrap_or(("s", 1));
+    format!("{}{}", secs / size, unit)
+}
+
+/// Prints the `limit` most recently modified matches under `directory` as paths, newest first
+/// (ties by path), each with its age: `./src/cache [3h ago]`. Returns whether anything matched.
+fn print_recent(directory: &str, opts: &ScanOptions, popts: &PrintOptions, limit: usize) -> bool {
+    let mut walker = matches(Path::new(directory), opts);
+    let mut found: Vec<(SystemTime, PathBuf, EntryKind)> = walker
+        .by_ref()
+        .map(|found| {
+            let modified = opts.files.metadata(&found.path).ok().and_then(|metadata| metadata.modified);
+            (modified.unwrap_or(UNIX_EPOCH), found.path, found.kind)
+        })
+        .collect();
+    if let Some(err) = walker.take_error() {
+        fail_scan(err);
+    }
+    found.sort_by(|(a_time, a_path, _), (b_time, b_path, _)| b_time.cmp(a_time).then_with(|| a_path.cmp(b_path)));
+    found.truncate(limit);
+    let now = SystemTime::now();
+    for (modified, path, _) in &found {
+        let shown = relative_path(path, popts.relative_to.as_deref()).display().to_string();
+        let line = hyperlink(if popts.color { highlight_root(&shown, popts) } else { shown }, path, popts);
+        let age = format_age(now.duration_since(*modified).unwrap_or_default());
+        println!("{}", fit_width(format!("{} [{} ago]", line, age), popts.max_width));
+    }
+    if popts.summary && !found.is_empty() {
+        let counted = found.iter().filter(|&&(_, _, kind)| popts.count.includes(kind)).count();
+        println!("\n{}", format_summary(popts.summary_format, counted as u32));
+    }
+    !found.is_empty()
+}
+
+/// Groups the matches under `directory` by name and prints each name that more than one of them
+/// has, with their paths (`--dedupe-names`), then a count. Returns whether any name is shared.
+fn report_duplicates(directory: &str, opts: &ScanOptions, popts: &PrintOptions) -> bool {
+    if opts.archive_format(Path::new(directory)).is_some() {
+        eprintln!("xtree: --dedupe-names cannot read archives");
+        process::exit(EXIT_ERROR);
+    }
+    let mut by_name: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let mut walker = matches(Path::new(directory), opts);
+    for found in walker.by_ref() {
+        if let Some(name) = found.path.file_name() {
+            by_name.entry(name.to_string_lossy().into_owned()).or_default().push(found.path);
+        }
+    }
+    if let Some(err) = walker.take_error() {
+        fail_scan(err);
+    }
+    by_name.retain(|_, paths| paths.len() > 1);
+    if by_name.is_empty() {
+        println!("No two matches share a name.");
+        return false;
+    }
+    let mut shared = 0;
+    for (name, paths) in &mut by_name {
+        paths.sort();
+        shared += paths.len();
+        let shown = if popts.color { highlight(name, popts) } else { name.clone() };
+        println!("{} ({})", shown, paths.len());
+        for path in paths.iter() {
+            println!("  {}", relative_path(path, popts.relative_to.as_deref()).display());
+        }
+    }
+    if popts.summary {
+        let names = if by_name.len() == 1 { "name" } else { "names" };
+        println!("\n{} {} shared by {} matches", by_name.len(), names, shared);
+    }
+    true
+}
+
+/// Prints the `expected` names (from `--expect`) that no entry under `directory` has, compared
+/// like search terms (ignoring case), followed by a count. Returns whether all were found.
+fn report_missing(directory: &str, opts: &ScanOptions, expected: &[String]) -> bool {
+    if opts.archive_format(Path::new(directory)).is_some() {
+        eprintln!("xtree: --expect cannot read archives");
+        process::exit(EXIT_ERROR);
+    }
+    let mut wanted: BTreeSet<String> = expected.iter().map(|name| opts.fold(name)).collect();
+    let total = wanted.len();
+    walk(Path::new(directory), opts, |info| {
+        if let Some(name) = info.path.file_name() {
+            wanted.remove(&opts.fold(&name.to_string_lossy()));
+        }
+        if wanted.is_empty() { Visit::Stop } else { Visit::Continue }
+    })
+    .unwrap_or_else(|err| fail_scan(err));
+    let missing: Vec<&String> = expected.iter().filter(|name| wanted.remove(&opts.fold(name))).collect();
+    for name in &missing {
+        println!("{}", name);
+    }
+    let noun = if total == 1 { "name" } else { "names" };
+    if missing.is_empty() {
+        println!("All {} expected {} found.", total, noun);
+    } else {
+        println!("\n{} of {} expected {} missing", missing.len(), total, noun);
+    }
+    missing.is_empty()
+}
+
+/// Prints how many files under `directory` have each extension (`--list-extensions`), whatever
+/// their names, most common first or with `--sort name` alphabetically (either reversed by
+/// `--reverse`), then a count. Files with no extension are listed as `(none)`. Returns whether
+/// there were any files.
+fn report_extensions(directory: &str, opts: &ScanOptions, popts: &PrintOptions) -> bool {
+    if opts.archive_format(Path::new(directory)).is_some() {
+        eprintln!("xtree: --list-extensions cannot read archives");
+        process::exit(EXIT_ERROR);
+    }
+    let mut opts = opts.clone();
+    opts.types = vec![EntryKind::File];
+    let mut by_extension: BTreeMap<String, usize> = BTreeMap::new();
+    walk(Path::new(directory), &opts, |info| {
+        let extension = info.path.extension().map_or_else(String::new, |ext| ext.to_string_lossy().into_owned());
+        *by_extension.entry(extension).or_default() += 1;
+        Visit::Continue
+    })
+    .unwrap_or_else(|err| fail_scan(err));
+    let mut rows: Vec<(String, usize)> = by_extension.into_iter().collect();
+    if popts.sort != Some("name") {
+        rows.sort_by_key(|&(_, count)| Reverse(count));
+    }
+    if popts.reverse {
+        rows.reverse();
+    }
+    let width = rows.iter().map(|(_, count)| count.to_string().len()).max().unwrap_or(1);
+    for (extension, count) in &rows {
+        let name = if extension.is_empty() { "(none)".to_string() } else { format!(".{}", extension) };
+        println!("{:>width$}  {}", count, name, width = width);
+    }
+    if popts.summary && !rows.is_empty() {
+        let files: usize = rows.iter().map(|(_, count)| count).sum();
+        let files_noun = if files == 1 { "file" } else { "files" };
+        let extensions_noun = if rows.len() == 1 { "extension" } else { "extensions" };
+        println!("\n{} {}, {} {}", files, files_noun, rows.len(), extensions_noun);
+    }
+    !rows.is_empty()
+}
+
+/// Runs `command` for every match under `directory` (`--exec`), or with `batch` once for all of
+/// them (`--exec-batch`), after the scan. Commands inherit stdout and stderr. A command that fails
+/// or cannot be started is reported and the others still run; if any did, exits with an error once
+/// all are done. Returns whether anything matched.
+fn exec_matches(directory: &str, opts: &ScanOptions, command: &[String], batch: bool) -> bool {
+    if opts.archive_format(Path::new(directory)).is_some() {
+        eprintln!("xtree: --exec and --exec-batch cannot read archives");
+        process::exit(EXIT_ERROR);
+    }
+    let mut walker = matches(Path::new(directory), opts);
+    let paths: Vec<PathBuf> = walker.by_ref().map(|found| found.path).collect();
+    if let Some(err) = walker.take_error() {
+        fail_scan(err);
+    }
+    let runs: Vec<Vec<OsString>> = if batch {
+        vec![command_line(command, &paths, true)]
+    } else {
+        paths.iter().map(|path| command_line(command, std::slice::from_ref(path), false)).collect()
+    };
+    let mut failed = 0;
+    for args in runs.iter().filter(|_| !paths.is_empty()) {
+        let shown = args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" ");
+        match process::Command::new(&args[0]).args(&args[1..]).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                eprintln!("xtree: '{}' failed: {}", shown, status);
+                failed += 1;
+            }
+            Err(err) => {
+                eprintln!("xtree: cannot run '{}': {}", shown, err);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        let noun = if runs.len() == 1 { "command" } else { "commands" };
+        eprintln!("xtree: {} of {} {} failed", failed, runs.len(), noun);
+        process::exit(EXIT_ERROR);
+    }
+    !paths.is_empty()
+}
+
+/// Fills `paths` into the words of `command`. For one path, every `{}` is replaced by it; with
+/// `batch`, a word that is exactly `{}` becomes all of them. With no such placeholder, the paths
+/// are appended.
+fn command_line(command: &[String], paths: &[PathBuf], batch: bool) -> Vec<OsString> {
+    let mut args = Vec::new();
+    let mut placed = false;
+    for word in command {
+        if word == "{}" {
+            args.extend(paths.iter().map(|path| path.as_os_str().to_owned()));
+            placed = true;
+        } else if !batch && word.contains("{}") {
+            args.push(word.replace("{}", &paths[0].to_string_lossy()).into());
+            placed = true;
+        } else {
+            args.push(word.into());
+        }
+    }
+    if !placed {
+        args.extend(paths.iter().map(|path| path.as_os_str().to_owned()));
+    }
+    args
+}
+
+/// Splits an `--exec` command into words at whitespace, keeping what is between single or
+/// double quotes together; there is no shell, so no other quoting or expansion.
+fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.get_or_insert_with(String::new).push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            None if c.is_whitespace() => words.extend(word.take()),
+            None => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(format!("unclosed {} quote", q));
+    }
+    words.extend(word);
+    if words.is_empty() {
+        return Err("empty command".to_string());
+    }
+    Ok(words)
+}
+
+/// Streams one JSON object per match as the scan finds it, flushing after each line so
+/// consumers can start on the first result before the walk finishes. Returns whether anything
+/// matched.
+fn print_ndjson(out: &mut impl Write, directory: &str, opts: &ScanOptions, base: Option<&Path>) -> io::Result<bool> {
+    let mut any = false;
+    let mut walker = matches(Path::new(directory), opts);
+    for found in walker.by_ref() {
+        any = true;
+        let record = NdjsonRecord {
+            id: node_id(found.path.strip_prefix(directory).unwrap_or(&found.path)),
+            path: relative_path(&found.path, base).to_string_lossy().into_owned(),
+            kind: found.kind,
+            depth: found.depth,
+            score: found.score,
+        };
+        serde_json::to_writer(&mut *out, &record)?;
+        writeln!(out)?;
+        out.flush()?;
+    }
+    if let Some(err) = walker.take_error() {
+        fail_scan(err);
+    }
+    Ok(any)
+}
+
+/// Reports the directory that stopped a `--strict` scan and exits.
+fn fail_scan(err: ScanError) -> ! {
+    eprintln!("xtree: {}", err);
+    process::exit(EXIT_ERROR);
+}
+
+/// Lists the files under `directory` that differ from `rev` (committed, staged or not), plus
+/// every directory leading to them, relative to `directory`.
+fn changed_since(directory: &str, rev: &str) -> Result<BTreeSet<PathBuf>, String> {
+    let inside = process::Command::new("git")
+        .args(["-C", directory, "rev-parse", "--is-inside-work-tree"])
+        .output()
+        .map_err(|err| format!("cannot run git: {}", err))?;
+    if !inside.status.success() {
+        return Err(format!("'{}' is not inside a git work tree", directory));
+    }
+    let diff = process::Command::new("git")
+        .args(["-C", directory, "diff", "--name-only", "--relative", "-z", rev, "--"])
+        .output()
+        .map_err(|err| format!("cannot run git: {}", err))?;
+    if !diff.status.success() {
+        return Err(String::from_utf8_lossy(&diff.stderr).trim().to_string());
+    }
+    let mut changed = BTreeSet::new();
+    for file in String::from_utf8_lossy(&diff.stdout).split('\0').filter(|file| !file.is_empty()) {
+        changed.extend(Path::new(file).ancestors().map(Path::to_path_buf));
+    }
+    Ok(changed)
+}
+
+/// The document printed by `--print-config`.
+#[derive(Serialize)]
+struct ResolvedConfig<'a> {
+    directory: &'a str,
+    scan: &'a ScanOptions,
+    output: &'a PrintOptions<'a>,
+}
+
+/// Prints the fully resolved options (command line plus defaults) to stderr as pretty JSON.
+fn print_config(directory: &str, opts: &ScanOptions, popts: &PrintOptions) -> io::Result<()> {
+    let config = ResolvedConfig {
+        directory,
+        scan: opts,
+        output: popts,
+    };
+    let mut err = io::stderr().lock();
+    serde_json::to_writer_pretty(&mut err, &config)?;
+    writeln!(err)
+}
+
+/// Loads search terms from `path`, one per line, skipping blank lines and `#` comments.
+fn read_terms_file(path: &str) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Loads `pattern=label` lines from a `--match-manifest` file, skipping blank lines and `#`
+/// comments. Patterns are lower-cased like search terms.
+fn read_manifest(path: &str) -> io::Result<Vec<(String, String)>> {
+    let mut labels = Vec::new();
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=').map(|(pattern, label)| (pattern.trim(), label.trim())) {
+            Some((pattern, label)) if !pattern.is_empty() && !label.is_empty() => {
+                labels.push((pattern.to_lowercase(), label.to_string()));
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: expected `pattern=label`", number + 1),
+                ))
+            }
+        }
+    }
+    Ok(labels)
+}
+
+/// Counts the matches carrying each label, in order of first appearance.
+fn tally_labels<'t>(node: &'t Tree, counts: &mut Vec<(&'t str, u32)>) {
+    for label in &node.labels {
+        match counts.iter_mut().find(|(seen, _)| seen == label) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((label, 1)),
+        }
+    }
+    for child in &node.children {
+        tally_labels(child, counts);
+    }
+}
+
+/// Prints how many matches carry each manifest label, e.g. `JS dep: 3, vendored: 1`.
+fn print_label_summary(tree: &Tree) {
+    let mut counts = Vec::new();
+    tally_labels(tree, &mut counts);
+    if !counts.is_empty() {
+        let counts: Vec<String> = counts.iter().map(|(label, count)| format!("{}: {}", label, count)).collect();
+        println!("{}", counts.join(", "));
+    }
+}
+
+/// Which side of a `--diff` an entry exists on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Presence {
+    Both,
+    /// Only below the first directory: removed.
+    OnlyA,
+    /// Only below the second directory: added.
+    OnlyB,
+}
+
+/// A node of the combined `--diff` tree.
+struct DiffNode<'t> {
+    tree: &'t Tree,
+    presence: Presence,
+    children: Vec<DiffNode<'t>>,
+}
+
+/// Aligns the children of the same directory below both roots by name. An entry shown on one
+/// side only is looked up in `files` on the other, so one that exists there but did not match
+/// (or has no matches below) still counts as present in both.
+fn diff_children<'t>(
+    files: &dyn FileSource,
+    a: &'t [Tree],
+    b: &'t [Tree],
+    a_path: &Path,
+    b_path: &Path,
+) -> Vec<DiffNode<'t>> {
+    let exists = |path: PathBuf| files.metadata(&path).is_ok();
+    let mut nodes = Vec::new();
+    for child in a {
+        let (a_child, b_child) = (a_path.join(&child.name), b_path.join(&child.name));
+        let other = b.iter().find(|other| other.name == child.name);
+        let presence = if other.is_some() || exists(b_child.clone()) {
+            Presence::Both
+        } else {
+            Presence::OnlyA
+        };
+        let other_children = other.map_or(&[][..], |other| &other.children);
+        nodes.push(DiffNode {
+            tree: child,
+            presence,
+            children: diff_children(files, &child.children, other_children, &a_child, &b_child),
+        });
+    }
+    for child in b.iter().filter(|child| !a.iter().any(|other| other.name == child.name)) {
+        let (a_child, b_child) = (a_path.join(&child.name), b_path.join(&child.name));
+        let presence = if exists(a_child.clone()) { Presence::Both } else { Presence::OnlyB };
+        nodes.push(DiffNode {
+            tree: child,
+            presence,
+            children: diff_children(files, &[], &child.children, &a_child, &b_child),
+        });
+    }
+    nodes
+}
+
+/// Prints a combined `--diff` tree: entries of both directories as usual, removed ones (first
+/// directory only) as `- name` in red and added ones as `+ name` in green. Adds the matches of
+/// each kind to `tally` as `(both, removed, added)`.
+fn print_diff_tree(nodes: &[DiffNode], popts: &PrintOptions, prefix: &str, tally: &mut [u32; 3]) {
+    let (corner, tee, pipe) = connectors(popts);
+    for (i, node) in nodes.iter().enumerate() {
+        let is_last = i == nodes.len() - 1;
+        let (branch, next_prefix) = if popts.indent_only {
+            ("  ", format!("{}  ", prefix))
+        } else if is_last {
+            (corner, format!("{}    ", prefix))
+        } else {
+            (tee, format!("{}{}", prefix, pipe))
+        };
+        let (slot, marker, color) = match node.presence {
+            Presence::Both => (0, "", ""),
+            Presence::OnlyA => (1, "- ", "\x1b[31m"),
+            Presence::OnlyB => (2, "+ ", "\x1b[32m"),
+        };
+        if node.tree.matched {
+            tally[slot] += 1;
+        }
+        let name = if node.presence == Presence::Both {
+            display_name(node.tree, popts)
+        } else if popts.color {
+            format!("{}{}{}\x1b[0m", color, marker, node.tree.name)
+        } else {
+            format!("{}{}", marker, node.tree.name)
+        };
+        let line = format!("{}{}{}", prefix, branch, annotate(name, node.tree));
+        println!("{}", fit_width(line, popts.max_width));
+        print_diff_tree(&node.children, popts, &next_prefix, tally);
+    }
+}
+
+/// Scans every directory of `--merge-roots` into `report` and overlays their trees, in order,
+/// into one named after the first that has matches. Returns `None` if none has any.
+fn merge_roots(dirs: &[&str], opts: &ScanOptions, report: &mut ScanReport) -> Option<Tree> {
+    dirs.iter()
+        .filter_map(|dir| build_tree_dict_with_report(dir, opts, report).unwrap_or_else(|err| fail_scan(err)))
+        .reduce(|mut merged, tree| {
+            merged.merge(tree);
+            merged
+        })
+}
+
+/// Scans both directories of `--diff` and prints their combined tree with a footer counting
+/// the matches found in both, removed and added. Returns whether anything matched.
+fn render_diff(a: &str, b: &str, opts: &ScanOptions, popts: &PrintOptions) -> bool {
+    let scan = |dir| {
+        build_tree_dict_with_report(dir, opts, &mut ScanReport::default()).unwrap_or_else(|err| fail_scan(err))
+    };
+    let (a_tree, b_tree) = (scan(a), scan(b));
+    if a_tree.is_none() && b_tree.is_none() {
+        println!("No directories match the search term.");
+        return false;
+    }
+    println!("--- {}\n+++ {}", a, b);
+    let a_children = a_tree.as_ref().map_or(&[][..], |tree| &tree.children);
+    let b_children = b_tree.as_ref().map_or(&[][..], |tree| &tree.children);
+    let nodes = diff_children(&*opts.files, a_children, b_children, Path::new(a), Path::new(b));
+    let mut tally = [0; 3];
+    print_diff_tree(&nodes, popts, "", &mut tally);
+    let [both, removed, added] = tally;
+    if popts.summary {
+        println!("\n{} in both, {} removed, {} added", both, removed, added);
+    }
+    true
+}
+
+/// The options of a search, shared by the top level (`xtree foo .`) and `xtree search`.
+fn search_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("search")
+            .help("Search term to filter directory names (if provided)")
+            .required(false)
+            .index(1),
+        Arg::new("directory")
+            .help("Directory to generate tree from (default: $XTREE_ROOT, else the current directory)")
+            .required(false)
+            .index(2),
+        Arg::new("diff")
+            .long("diff")
+            .value_names(&["DIR_A", "DIR_B"])
+            .help("Compare two directories: show their matches as one tree, marking those only in DIR_A (-) or DIR_B (+)")
+            .takes_value(true)
+            .number_of_values(2)
+            .conflicts_with_all(&["directory", "format", "watch", "breadth-first", "tips", "template"]),
+        Arg::new("anonymize")
+            .long("anonymize")
+            .help("Replace the names of directories that did not match with placeholders, for sharing the output")
+            .conflicts_with_all(&["rank", "recent", "grep", "breadcrumbs", "socket", "diff"]),
+        Arg::new("merge-roots")
+            .long("merge-roots")
+            .value_name("DIR")
+            .help("Scan several directories and overlay their matches into one tree, entries with the same path shown once")
+            .takes_value(true)
+            .multiple_values(true)
+            .min_values(2)
+            .conflicts_with_all(&["directory", "diff", "watch", "rank", "recent", "grep", "breadcrumbs", "expand", "socket", "tips"]),
+        Arg::new("archive")
+            .long("archive")
+            .value_name("FORMAT")
+            .help("Scan the directory argument as an archive of this format (default: by extension)")
+            .takes_value(true)
+            .possible_values(["zip", "tar", "tar.gz"]),
+        Arg::new("depth")
+            .short('d')
+            .long("depth")
+            .alias("traverse-depth")
+            .help("Maximum depth of directory tree (default: 3)")
+            .takes_value(true)
+            .default_value("3"),
+        Arg::new("scan-budget")
+            .long("scan-budget")
+            .value_name("N")
+            .help("Stop scanning after listing N directories, and show what was found so far")
+            .takes_value(true)
+            .conflicts_with_all(&["dedupe-names", "expect", "exec", "exec-batch", "list-extensions"]),
+        Arg::new("display-depth")
+            .long("display-depth")
+            .value_name("N")
+            .help("Only draw the tree down to depth N; deeper matches are still counted")
+            .takes_value(true),
+        Arg::new("max-matches-per-dir")
+            .long("max-matches-per-dir")
+            .value_name("N")
+            .help("Show at most N matching files per directory, then how many more there are")
+            .takes_value(true),
+        Arg::new("flatten-depth")
+            .long("flatten-depth")
+            .value_name("N")
+            .help("Collapse everything below depth N into a match count on the depth-N nodes")
+            .takes_value(true)
+            .conflicts_with("tips"),
+        Arg::new("sample")
+            .long("sample")
+            .value_name("N")
+            .help("Draw only the first N matches at each depth, still descending below the others")
+            .takes_value(true)
+            .conflicts_with_all(&["rank", "recent", "grep", "breadcrumbs", "tips", "format", "diff", "dedupe-names", "socket"]),
+        Arg::new("dedupe-names")
+            .long("dedupe-names")
+            .help("Instead of the tree, list the names that several matches share, with their paths")
+            .conflicts_with_all(&["rank", "recent", "grep", "breadcrumbs", "tips", "format", "diff", "watch", "expand", "socket", "merge-roots", "expect"]),
+        Arg::new("list-extensions")
+            .long("list-extensions")
+            .help("Instead of searching, count the files of each extension down to --depth, most common first")
+            .conflicts_with_all(&["rank", "recent", "grep", "breadcrumbs", "tips", "format", "diff", "watch", "expand", "socket", "merge-roots", "expect", "dedupe-names"]),
+        Arg::new("exec")
+            .long("exec")
+            .value_name("CMD")
+            .help("Instead of the tree, run CMD for each match, with {} replaced by its path (or the path appended)")
+            .takes_value(true)
+            .conflicts_with_all(&["exec-batch", "rank", "recent", "grep", "breadcrumbs", "tips", "format", "diff", "watch", "expand", "socket", "merge-roots", "expect", "dedupe-names", "list-extensions"]),
+        Arg::new("exec-batch")
+            .long("exec-batch")
+            .value_name("CMD")
+            .help("Instead of the tree, run CMD once with the paths of all matches in place of {} (or appended)")
+            .takes_value(true)
+            .conflicts_with_all(&["rank", "recent", "grep", "breadcrumbs", "tips", "format", "diff", "watch", "expand", "socket", "merge-roots", "expect", "dedupe-names", "list-extensions"]),
+        Arg::new("summary-tree")
+            .long("summary-tree")
+            .help("Draw only the matches and the paths to them, folding chains of single directories into one line")
+            .conflicts_with_all(&["tips", "show-empty"]),
+        Arg::new("term")
+            .short('e')
+            .long("term")
+            .help("Additional search term; repeatable, terms are OR-ed together unless --match all")
+            .takes_value(true)
+            .multiple_occurrences(true),
+        Arg::new("split-terms")
+            .long("split-terms")
+            .value_name("DELIM")
+            .help("Split the search term at each DELIM (default: ',') into several terms, any of which may match")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .default_missing_value(","),
+        Arg::new("not-term")
+            .long("not-term")
+            .value_name("TERM")
+            .help("Rule out names containing TERM, whatever else they match; repeatable")
+            .takes_value(true)
+            .multiple_occurrences(true),
+        Arg::new("match")
+            .long("match")
+            .value_name("MODE")
+            .help("Whether a name needs any search term or all of them (default: any)")
+            .takes_value(true)
+            .possible_values(["any", "all"]),
+        Arg::new("max-distance")
+            .long("max-distance")
+            .value_name("N")
+            .help("Tolerate typos: let a term match any part of a name at most N character edits away")
+            .takes_value(true),
+        Arg::new("min-match-length")
+            .long("min-match-length")
+            .value_name("N")
+            .help("Refuse search terms shorter than N characters, which match nearly everything [default: 1]")
+            .takes_value(true),
+        Arg::new("force")
+            .long("force")
+            .help("Search even with terms shorter than --min-match-length"),
+        Arg::new("fixed")
+            .long("fixed")
+            .value_name("TERM")
+            .help("Search term taken literally, even if it starts with '-'")
+            .takes_value(true)
+            .allow_hyphen_values(true),
+        Arg::new("replace")
+            .long("replace")
+            .value_names(&["FROM", "TO"])
+            .help("Search for FROM and preview each match renamed with FROM replaced by TO")
+            .takes_value(true)
+            .number_of_values(2)
+            .conflicts_with("expect"),
+        Arg::new("apply")
+            .long("apply")
+            .help("Actually perform the renames previewed by --replace")
+            .requires("replace")
+            // Only the tree view performs the renames; every other output would skip them.
+            .conflicts_with_all(&["diff", "anonymize", "merge-roots", "dedupe-names", "list-extensions", "exec", "exec-batch", "expand", "watch", "format", "tips", "rank", "recent", "grep", "breadcrumbs", "socket"]),
+        Arg::new("match-manifest")
+            .long("match-manifest")
+            .value_name("FILE")
+            .help("Search for the patterns of a `pattern=label` file and tag matches with their labels")
+            .takes_value(true),
+        Arg::new("terms-file")
+            .long("terms-file")
+            .value_name("FILE")
+            .help("Read search terms from FILE, one per line (blank lines and # comments ignored)")
+            .takes_value(true),
+        Arg::new("expect")
+            .long("expect")
+            .value_name("FILE")
+            .help("Instead of matches, list the names in FILE (one per line) that no entry in the tree has")
+            .takes_value(true)
+            .conflicts_with_all(&["diff", "watch", "rank", "recent", "tips", "format"]),
+        Arg::new("filter")
+            .long("filter")
+            .value_name("EXPR")
+            .help("Only match directories satisfying EXPR, e.g. 'name~foo && depth<4 && mtime<7d'")
+            .takes_value(true),
+        Arg::new("normalize")
+            .long("normalize")
+            .help("Compare names and terms in Unicode NFC form (default on macOS)")
+            .overrides_with("no-normalize"),
+        Arg::new("no-normalize")
+            .long("no-normalize")
+            .help("Compare names byte for byte, without Unicode normalization")
+            .overrides_with("normalize"),
+        Arg::new("ignore-accents")
+            .long("ignore-accents")
+            .alias("ascii-fold")
+            .help("Ignore diacritics when matching, so 'cafe' matches 'café'"),
+        Arg::new("case-sensitive")
+            .long("case-sensitive")
+            .short('s')
+            .help("Match search terms with their case")
+            .overrides_with_all(&["ignore-case", "smart-case"]),
+        Arg::new("ignore-case")
+            .long("ignore-case")
+            .short('i')
+            .help("Match search terms regardless of case (the default)")
+            .overrides_with_all(&["case-sensitive", "smart-case"]),
+        Arg::new("smart-case")
+            .long("smart-case")
+            .short('S')
+            .help("Match search terms with their case only if one contains an uppercase letter")
+            .overrides_with_all(&["case-sensitive", "ignore-case"]),
+        Arg::new("highlight-ignore-case")
+            .long("highlight-ignore-case")
+            .help("Highlight search terms regardless of case, even when matching with it"),
+        Arg::new("min-children")
+            .long("min-children")
+            .value_name("N")
+            .help("Only match directories with at least N immediate subdirectories")
+            .takes_value(true),
+        Arg::new("empty")
+            .long("empty")
+            .help("Only match empty directories"),
+        Arg::new("only-dirs-with-files")
+            .long("only-dirs-with-files")
+            .help("With --type f, match the directories that directly hold a matching file instead of by name")
+            .conflicts_with_all(&["dedupe-names", "expect", "exec", "exec-batch", "list-extensions", "empty", "min-children"]),
+        Arg::new("xattr")
+            .long("xattr")
+            .value_name("NAME[=VALUE]")
+            .help("Only match entries with the extended attribute NAME (set to VALUE, if given)")
+            .takes_value(true),
+        Arg::new("owner")
+            .long("owner")
+            .value_name("NAME|UID")
+            .help("Only match entries owned by this user")
+            .takes_value(true),
+        Arg::new("group")
+            .long("group")
+            .value_name("NAME|GID")
+            .help("Only match entries owned by this group")
+            .takes_value(true),
+        Arg::new("larger-than")
+            .long("larger-than")
+            .value_name("SIZE")
+            .help("Only match entries whose total size is above SIZE, e.g. 10M or 1.5G")
+            .takes_value(true),
+        Arg::new("smaller-than")
+            .long("smaller-than")
+            .value_name("SIZE")
+            .help("Only match entries whose total size is below SIZE, e.g. 1K")
+            .takes_value(true),
+        Arg::new("name-longer-than")
+            .long("name-longer-than")
+            .value_name("N")
+            .help("Only match entries whose name is longer than N characters")
+            .takes_value(true),
+        Arg::new("name-shorter-than")
+            .long("name-shorter-than")
+            .value_name("N")
+            .help("Only match entries whose name is shorter than N characters")
+            .takes_value(true),
+        Arg::new("min-lines")
+            .long("min-lines")
+            .value_name("N")
+            .help("Only match regular, non-binary files of at least N lines (reads every candidate file)")
+            .takes_value(true),
+        Arg::new("max-lines")
+            .long("max-lines")
+            .value_name("N")
+            .help("Only match regular, non-binary files of at most N lines (reads every candidate file)")
+            .takes_value(true),
+        Arg::new("since-commit")
+            .long("since-commit")
+            .value_name("REF")
+            .help("Only match directories containing files changed since the git commit REF")
+            .takes_value(true),
+        Arg::new("type")
+            .short('t')
+            .long("type")
+            .value_name("TYPE")
+            .help("Match only entries of TYPE, like find -type: d (directories), f (files), l (symlinks); comma-separated or repeatable")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .possible_values(["d", "f", "l"]),
+        Arg::new("perms")
+            .long("perms")
+            .help("Show the permission bits of each directory"),
+        Arg::new("same-filesystem")
+            .short('x')
+            .long("same-filesystem")
+            .help("Do not descend into directories on other filesystems"),
+        Arg::new("min-score")
+            .long("min-score")
+            .value_name("N")
+            .help("Only show branches containing at least N matches (default: 1)")
+            .takes_value(true)
+            .default_value("1"),
+        Arg::new("show-empty")
+            .long("show-empty")
+            .help("Also show directories without matches, dimmed, for context"),
+        Arg::new("indent-only")
+            .long("indent-only")
+            .help("Indent with two spaces per level instead of drawing tree connectors"),
+        Arg::new("depth-colors")
+            .long("depth-colors")
+            .value_name("MODE")
+            .help("Color tree connectors by level, counted from the root (absolute) or from each branch's first match (relative)")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .default_missing_value("absolute")
+            .possible_values(["absolute", "relative"]),
+        Arg::new("rtl")
+            .long("rtl")
+            .help("Mirror the tree for right-to-left reading: names first, right-aligned, connectors to their right")
+            .conflicts_with_all(&["template", "max-width", "breadth-first", "tips", "rank", "recent", "grep", "breadcrumbs", "format", "diff", "socket"]),
+        Arg::new("ascii")
+            .long("ascii")
+            .help("Draw tree connectors and breadcrumb separators with ASCII characters only"),
+        Arg::new("hyperlinks")
+            .long("hyperlinks")
+            .help("Make names clickable links to their directories (OSC 8) on terminals with colors"),
+        Arg::new("max-width")
+            .long("max-width")
+            .value_name("COLS")
+            .help("Truncate lines longer than COLS characters with '…', whatever the terminal width")
+            .takes_value(true),
+        Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Output format")
+            .takes_value(true)
+            .possible_values(["tree", "json", "ndjson", "yaml", "org", "html", "matches-json"])
+            .default_value("tree"),
+        Arg::new("json-pretty")
+            .long("json-pretty")
+            .help("Indent JSON output for reading instead of printing it on one line"),
+        Arg::new("histogram")
+            .long("histogram")
+            .help("After the tree, show how many matches each depth holds as a bar chart"),
+        Arg::new("tips")
+            .long("tips")
+            .help("Only list the deepest match of each branch (a match with no matches below), as full paths"),
+        Arg::new("rank")
+            .long("rank")
+            .help("List all matches as paths, those containing the most terms (then occurrences) first")
+            .conflicts_with_all(&["tips", "breadth-first", "template", "diff", "format", "flatten-depth"]),
+        Arg::new("grep")
+            .long("grep")
+            .help("List matches as highlighted paths like grep, with no footer; with --rank, most relevant first")
+            .conflicts_with_all(&["recent", "tips", "breadth-first", "template", "diff", "format", "flatten-depth"]),
+        Arg::new("breadcrumbs")
+            .long("breadcrumbs")
+            .help("List matches as their chains of ancestors, e.g. 'root › src › test', with no footer")
+            .conflicts_with_all(&["grep", "recent", "tips", "breadth-first", "template", "diff", "format", "flatten-depth"]),
+        Arg::new("breadcrumb-separator")
+            .long("breadcrumb-separator")
+            .value_name("SEP")
+            .help("Separate --breadcrumbs with SEP (default: '›', or '>' with --ascii)")
+            .takes_value(true)
+            .requires("breadcrumbs"),
+        Arg::new("recent")
+            .long("recent")
+            .value_name("N")
+            .help("List only the N most recently modified matches as paths, newest first")
+            .takes_value(true)
+            .conflicts_with_all(&["rank", "tips", "breadth-first", "template", "diff", "format", "flatten-depth"]),
+        Arg::new("sort")
+            .long("sort")
+            .value_name("ORDER")
+            .help("Order each directory's entries by modification time, oldest (mtime) or newest (mtime-desc) first; order --list-extensions by name or count")
+            .takes_value(true)
+            .possible_values(["mtime", "mtime-desc", "name", "count"])
+            .conflicts_with_all(&["rank", "recent", "grep", "breadcrumbs", "socket", "diff"]),
+        Arg::new("reverse")
+            .short('r')
+            .long("reverse")
+            .help("Reverse the order of each directory's entries (after --sort), or of the --list-extensions table")
+            .conflicts_with_all(&["rank", "recent", "grep", "breadcrumbs", "socket", "diff"]),
+        Arg::new("socket")
+            .long("socket")
+            .value_name("ADDR")
+            .help("Send the matches as NDJSON to a Unix domain socket path or a HOST:PORT over TCP")
+            .takes_value(true)
+            .conflicts_with_all(&["format", "rank", "recent", "grep", "breadcrumbs", "tips", "diff"]),
+        Arg::new("expand")
+            .long("expand")
+            .help("Draw matches one level at a time, going one level deeper on each Enter")
+            .conflicts_with_all(&["watch", "rank", "recent", "grep", "breadcrumbs", "tips", "format", "diff", "socket"]),
+        Arg::new("watch")
+            .long("watch")
+            .help("Keep running and redraw whenever something below the directory changes"),
+        Arg::new("log-level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .help("Log scanning details to stderr at this level or above")
+            .takes_value(true)
+            .possible_values(["off", "error", "warn", "info", "debug", "trace"]),
+        Arg::new("relative-to")
+            .long("relative-to")
+            .value_name("BASE")
+            .help("Print the paths of --tips, --rank, --grep, --recent, --breadcrumbs, --dedupe-names, --format ndjson, --format matches-json and --template's {path} relative to BASE")
+            .takes_value(true),
+        Arg::new("quiet-errors")
+            .long("quiet-errors")
+            .help("Do not report directories skipped because they could not be read"),
+        Arg::new("report-depth")
+            .long("report-depth")
+            .help("Tell on stderr the deepest level the scan reached, and whether --depth cut it short"),
+        Arg::new("stats-json")
+            .long("stats-json")
+            .value_name("FILE")
+            .help("Also write scan statistics (matches, directories scanned, timing, ...) to FILE as JSON")
+            .takes_value(true),
+        Arg::new("strict")
+            .long("strict")
+            .help("Fail with exit status 2 on the first directory that cannot be read instead of skipping it"),
+        Arg::new("yes")
+            .long("yes")
+            .short('y')
+            .help("Scan a filesystem root such as / at depth 5 or more without asking first"),
+        Arg::new("print-config")
+            .long("print-config")
+            .help("Print the resolved options as JSON to stderr and exit without scanning"),
+        Arg::new("breadth-first")
+            .long("breadth-first")
+            .help("List all matches of each depth before the next depth, as indented paths")
+            .overrides_with("depth-first"),
+        Arg::new("depth-first")
+            .long("depth-first")
+            .help("List matches following the directory structure (default)")
+            .overrides_with("breadth-first"),
+        Arg::new("summary-format")
+            .long("summary-format")
+            .value_name("FORMAT")
+            .help("Footer template: {count} is the match count, {one|many} picks a word by count")
+            .takes_value(true),
+        Arg::new("template")
+            .long("template")
+            .value_name("TEMPLATE")
+            .help("Print each node as TEMPLATE, with {name}, {path}, {depth}, {score}, {prefix} and {branch} filled in")
+            .takes_value(true)
+            .conflicts_with_all(&["breadth-first", "tips"]),
+        Arg::new("count")
+            .long("count")
+            .value_name("WHAT")
+            .help("What the footer counts: matching dirs, files (and symlinks) or all of them")
+            .possible_values(["dirs", "files", "all"])
+            .takes_value(true),
+        Arg::new("no-summary")
+            .long("no-summary")
+            .help("Do not print the footer line with the number of matches"),
+        Arg::new("legend")
+            .long("legend")
+            .help("Print a key explaining the colors after the tree"),
+        Arg::new("highlight-bg")
+            .long("highlight-bg")
+            .value_name("COLOR")
+            .help("Also give highlighted matches a background color, e.g. blue or bright-yellow")
+            .takes_value(true),
+        Arg::new("color")
+            .long("color")
+            .value_name("WHEN")
+            .help("When to highlight matches with ANSI colors")
+            .takes_value(true)
+            .possible_values(["auto", "always", "never"])
+            .default_value("auto"),
+        Arg::new("no-color")
+            .long("no-color")
+            .help("Never use colors (same as NO_COLOR); overrides --color"),
+        Arg::new("smart-ignore")
+            .long("smart-ignore")
+            .value_name("NAMES")
+            .help("Show build, dependency and VCS directories (target, node_modules, .git, ...) collapsed instead of scanning them; NAMES replaces the list, +NAMES extends it")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .default_missing_value("")
+            .overrides_with("no-smart-ignore"),
+        Arg::new("no-smart-ignore")
+            .long("no-smart-ignore")
+            .help("Scan the directories --smart-ignore collapses")
+            .overrides_with("smart-ignore"),
+        Arg::new("include")
+            .long("include")
+            .value_name("NAME")
+            .help("Scan entries with this exact name even if an ignore pattern excludes them; repeatable")
+            .takes_value(true)
+            .multiple_occurrences(true),
+        Arg::new("no-xtreeignore")
+            .long("no-xtreeignore")
+            .help("Do not read .xtreeignore files"),
+        Arg::new("cache")
+            .long("cache")
+            .help("Reuse the directory structure cached by earlier --cache runs, re-reading only modified directories"),
+        Arg::new("clear-cache")
+            .long("clear-cache")
+            .help("Delete all cached directory structures and exit"),
+        Arg::new("no-count-root")
+            .long("no-count-root")
+            .help("Do not match or count the root directory itself"),
+        Arg::new("list-options")
+            .long("list-options")
+            .help("Print every long option, one per line, for shell completion")
+            .hide(true),
+    ]
+}
+
+/// Builds the command line: a search by default, as well as the `search`, `tree` and
+/// `completions` subcommands.
+fn cli() -> App<'static> {
+    // `xtree tree` lists everything, so it takes none of the options that supply search terms
+    // and the directory comes first.
+    let tree_args = search_args().into_iter().filter(|arg| {
+        !matches!(arg.get_id(), "search" | "directory" | "term" | "split-terms" | "fixed" | "replace" | "apply" | "terms-file")
+    });
+    App::new("xtree")
+        .version("0.1")
+        .about("A lightweight directory tree generator.")
+        .after_help(
+            "A first argument naming a subcommand (search, tree, completions, help) is taken as one: \
+             `xtree tree .` lists every directory, and `xtree search tree .` looks for ones named tree.",
+        )
+        .args(search_args())
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            App::new("search")
+                .about("Show the directories whose names match the search terms (the default)")
+                .args(search_args()),
+        )
+        .subcommand(
+            App::new("tree")
+                .about("Show every directory, without filtering")
+                .arg(
+                    Arg::new("directory")
+                        .help("Directory to generate tree from (default: $XTREE_ROOT, else the current directory)")
+                        .index(1),
+                )
+                .args(tree_args),
+        )
+        .subcommand(
+            App::new("completions")
+                .about("Print a completion script for SHELL")
+                .arg(
+                    Arg::new("shell")
+                        .value_name("SHELL")
+                        .possible_values(["bash", "elvish", "fish", "powershell", "zsh"])
+                        .required(true),
+                ),
+        )
+}
+
+fn main() {
+    // Clone the app so that we can later print the help message.
+    let mut app = cli();
+
+    let top = app.clone().get_matches();
+    let subcommand = top.subcommand().map(|(name, sub)| (name.to_string(), sub.clone()));
+    let (matches, list_all) = match subcommand {
+        Some((name, sub)) if name == "completions" => {
+            let shell: Shell = sub.value_of("shell").and_then(|shell| shell.parse().ok()).expect("checked by clap");
+            clap_complete::generate(shell, &mut app, "xtree", &mut io::stdout());
+            return;
+        }
+        Some((name, sub)) => (sub, name == "tree"),
+        // Without a subcommand, the top level accepts everything `xtree search` does.
+        None => (top, false),
+    };
+
+    if matches.is_present("list-options") {
+        // Building adds the generated --help and --version.
+        app.build();
+        for arg in app.get_arguments() {
+            let aliases = arg.get_all_aliases().unwrap_or_default();
+            for long in arg.get_long().into_iter().chain(aliases) {
+                println!("--{}", long);
+            }
+        }
+        return;
+    }
+
+    // Without --log-level, RUST_LOG still works as usual.
+    let mut logger = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("off"));
+    if let Some(level) = matches.value_of("log-level") {
+        logger.filter_level(level.parse().unwrap_or(LevelFilter::Off));
+    }
+    logger.init();
+
+    if matches.is_present("clear-cache") {
+        if let Err(err) = clear_cache() {
+            eprintln!("xtree: cannot clear the cache: {}", err);
+            process::exit(EXIT_ERROR);
+        }
+        return;
+    }
+
+    // With explicit terms (--fixed, --term, ...) or match conditions (--filter, --empty, ...), a lone
+    // positional argument is the directory.
+    // `xtree tree` has none of the options that supply search terms.
+    let search_matches = (!list_all).then_some(&matches);
+    let explicit_terms = search_matches.is_some_and(|matches| {
+        matches.is_present("fixed") || matches.is_present("replace") || matches.is_present("term") || matches.is_present("terms-file")
+    }) || matches.is_present("not-term")
+        || matches.is_present("match-manifest")
+        || matches.is_present("min-children")
+        || matches.is_present("empty")
+        || matches.is_present("larger-than")
+        || matches.is_present("smaller-than")
+        || matches.is_present("name-longer-than")
+        || matches.is_present("name-shorter-than")
+        || matches.is_present("min-lines")
+        || matches.is_present("max-lines")
+        || matches.is_present("expect")
+        || matches.is_present("xattr")
+        || matches.is_present("owner")
+        || matches.is_present("group")
+        || matches.is_present("filter")
+        || matches.is_present("list-extensions");
+    let (search, directory) = if list_all {
+        (None, matches.value_of("directory"))
+    } else if explicit_terms
+        && !matches.is_present("directory")
+        && !matches.is_present("diff")
+        && !matches.is_present("merge-roots")
+    {
+        (None, matches.value_of("search"))
+    } else {
+        (matches.value_of("search"), matches.value_of("directory"))
+    };
+
+    let replace: Option<Vec<&str>> = search_matches.and_then(|matches| matches.values_of("replace")).map(Iterator::collect);
+    let replace_from = replace.as_ref().map(|replace| replace[0].to_lowercase());
+    let search: Vec<&str> = match search_matches.and_then(|matches| matches.value_of("split-terms")) {
+        Some(delim) if !delim.is_empty() => {
+            search.into_iter().flat_map(|search| search.split(delim)).filter(|term| !term.is_empty()).collect()
+        }
+        _ => search.into_iter().collect(),
+    };
+    let fixed = search_matches.and_then(|matches| matches.value_of("fixed"));
+    let mut terms: Vec<String> = search.into_iter().chain(fixed).map(str::to_owned).collect();
+    terms.extend(replace.as_ref().map(|replace| replace[0].to_owned()));
+    terms.extend(search_matches.and_then(|matches| matches.values_of("term")).into_iter().flatten().map(str::to_owned));
+    if let Some(file) = search_matches.and_then(|matches| matches.value_of("terms-file")) {
+        match read_terms_file(file) {
+            Ok(file_terms) => terms.extend(file_terms),
+            Err(err) => {
+                eprintln!("xtree: cannot read terms file {}: {}", file, err);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    if let Some(min) = matches.value_of("min-match-length") {
+        let Ok(min) = min.parse::<usize>() else {
+            eprintln!("xtree: --min-match-length expects a number, got {}", min);
+            process::exit(EXIT_ERROR);
+        };
+        let short = terms.iter().find(|term| term.chars().count() < min);
+        if let Some(term) = short.filter(|_| !matches.is_present("force")) {
+            eprintln!("xtree: search term '{}' is shorter than --min-match-length {}; use --force to search anyway", term, min);
+            process::exit(EXIT_ERROR);
+        }
+    }
+    let mut labels = Vec::new();
+    if let Some(file) = matches.value_of("match-manifest") {
+        match read_manifest(file) {
+            Ok(manifest) => labels = manifest,
+            Err(err) => {
+                eprintln!("xtree: cannot read manifest {}: {}", file, err);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    terms.extend(labels.iter().map(|(pattern, _)| pattern.clone()));
+    let excluded: Vec<&str> = matches.values_of("not-term").into_iter().flatten().collect();
+    let case_sensitive = matches.is_present("case-sensitive")
+        || (matches.is_present("smart-case")
+            && terms.iter().map(String::as_str).chain(excluded.iter().copied()).any(|term| term.chars().any(char::is_uppercase)));
+    let mut opts = if case_sensitive {
+        ScanOptions::new_case_sensitive(&terms)
+    } else {
+        ScanOptions::new(&terms)
+    };
+    opts.labels = labels;
+    opts.excluded = excluded.iter().map(|term| opts.fold(term)).collect();
+    opts.match_all = matches.value_of("match") == Some("all");
+    opts.all = list_all;
+    if let Some(expr) = matches.value_of("filter") {
+        match Filter::parse(expr) {
+            Ok(filter) => opts.filter = Some(filter),
+            Err(err) => {
+                eprintln!("xtree: invalid --filter expression: {}", err);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    if let Some(min) = matches.value_of("min-children") {
+        match min.parse() {
+            Ok(min) if min > 0 => opts.min_children = Some(min),
+            _ => {
+                eprintln!("xtree: --min-children expects a positive number, got {}", min);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    opts.empty = matches.is_present("empty");
+    opts.only_dirs_with_files = matches.is_present("only-dirs-with-files");
+    opts.strict = matches.is_present("strict");
+    opts.scan_budget = matches.value_of("scan-budget").map(|budget| number_arg("scan-budget", budget, true));
+    let list_extensions = matches.is_present("list-extensions");
+    match matches.value_of("sort") {
+        Some(order @ ("mtime" | "mtime-desc")) if list_extensions => {
+            eprintln!("xtree: --list-extensions sorts by name or count, not {}", order);
+            process::exit(EXIT_ERROR);
+        }
+        Some(order @ ("name" | "count")) if !list_extensions => {
+            eprintln!("xtree: --sort {} only applies to --list-extensions", order);
+            process::exit(EXIT_ERROR);
+        }
+        _ => {}
+    }
+    opts.mtimes = matches.is_present("sort") && !list_extensions;
+    if let Some(max) = matches.value_of("max-distance") {
+        match max.parse() {
+            Ok(max) => opts.max_distance = Some(max).filter(|&max| max > 0),
+            Err(_) => {
+                eprintln!("xtree: --max-distance expects a number, got {}", max);
+                process::exit(EXIT_ERROR);
+            }
+        }
+        if opts.max_distance.is_some() {
+            eprintln!("xtree: --max-distance compares every name edit by edit; expect large trees to scan slower");
+        }
+    }
+    for (flag, bound) in [("larger-than", &mut opts.larger_than), ("smaller-than", &mut opts.smaller_than)] {
+        if let Some(value) = matches.value_of(flag) {
+            match parse_size(value) {
+                Some(bytes) => *bound = Some(bytes),
+                None => {
+                    eprintln!("xtree: invalid --{} size {} (use e.g. 512K, 10M, 1.5G)", flag, value);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+    }
+    for (flag, bound) in [
+        ("name-longer-than", &mut opts.name_longer_than),
+        ("name-shorter-than", &mut opts.name_shorter_than),
+    ] {
+        if let Some(value) = matches.value_of(flag) {
+            match value.parse() {
+                Ok(chars) => *bound = Some(chars),
+                Err(_) => {
+                    eprintln!("xtree: --{} expects a number of characters, got {}", flag, value);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+    }
+    for (flag, bound) in [("min-lines", &mut opts.min_lines), ("max-lines", &mut opts.max_lines)] {
+        if let Some(value) = matches.value_of(flag) {
+            match value.parse() {
+                Ok(lines) => *bound = Some(lines),
+                Err(_) => {
+                    eprintln!("xtree: --{} expects a number of lines, got {}", flag, value);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+    }
+    if let Some(spec) = matches.value_of("xattr") {
+        let (name, value) = match spec.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (spec, None),
+        };
+        if XATTR_SUPPORTED {
+            opts.xattr = Some((name.to_string(), value));
+        } else {
+            eprintln!("xtree: --xattr is not supported on this platform; ignoring");
+        }
+    }
+    for (flag, id) in [("owner", &mut opts.owner), ("group", &mut opts.group)] {
+        let Some(spec) = matches.value_of(flag) else {
+            continue;
+        };
+        if !cfg!(unix) {
+            eprintln!("xtree: --{} is not supported on this platform; ignoring", flag);
+            continue;
+        }
+        match resolve_id(spec, flag == "group") {
+            Some(found) => *id = Some(found),
+            None => {
+                eprintln!("xtree: unknown {} {}", if flag == "group" { "group" } else { "user" }, spec);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    let structural = opts.min_children.is_some()
+        || opts.empty
+        || opts.larger_than.is_some()
+        || opts.smaller_than.is_some()
+        || opts.name_longer_than.is_some()
+        || opts.name_shorter_than.is_some()
+        || opts.min_lines.is_some()
+        || opts.max_lines.is_some()
+        || opts.xattr.is_some()
+        || opts.owner.is_some()
+        || opts.group.is_some()
+        || !opts.excluded.is_empty();
+    let expected = matches.value_of("expect").map(|file| match read_terms_file(file) {
+        Ok(names) => names,
+        Err(err) => {
+            eprintln!("xtree: cannot read expected names {}: {}", file, err);
+            process::exit(EXIT_ERROR);
+        }
+    });
+    if opts.terms_lower.is_empty() && opts.filter.is_none() && !structural && !opts.all && expected.is_none() && !list_extensions {
+        app.print_help().expect("Failed to print help");
+        println!();
+        return;
+    }
+
+    let diff: Option<Vec<&str>> = matches.values_of("diff").map(Iterator::collect);
+    let merged: Vec<&str> = matches.values_of("merge-roots").into_iter().flatten().collect();
+    // The root is DIR_A of --diff or the first --merge-roots directory, else the directory
+    // argument, else $XTREE_ROOT, else `.`; root-relative options such as --since-commit and
+    // --same-filesystem follow it.
+    let env_root = env::var("XTREE_ROOT").ok().filter(|root| !root.is_empty());
+    let directory = diff
+        .as_ref()
+        .map(|dirs| dirs[0])
+        .or(merged.first().copied())
+        .or(directory)
+        .or(env_root.as_deref())
+        .unwrap_or(".");
+    opts.archive = matches.value_of("archive").map(|format| match format {
+        "zip" => ArchiveFormat::Zip,
+        "tar" => ArchiveFormat::Tar,
+        _ => ArchiveFormat::TarGz,
+    });
+    for dir in diff.iter().flatten().chain(&merged).copied().chain([directory]) {
+        let readable = match opts.archive_format(Path::new(dir)) {
+            Some(_) => fs::File::open(dir).map(drop),
+            None => fs::read_dir(dir).map(drop),
+        };
+        if let Err(err) = readable {
+            eprintln!("xtree: cannot read directory {}: {}", dir, err);
+            process::exit(EXIT_ERROR);
+        }
+    }
+    let depth: usize = matches
+        .value_of("depth")
+        .unwrap_or("3")
+        .parse()
+        .unwrap_or(3);
+
+    opts.max_depth = depth;
+    if let Some(types) = matches.values_of("type") {
+        opts.types = types
+            .map(|kind| match kind {
+                "f" => EntryKind::File,
+                "l" => EntryKind::Symlink,
+                _ => EntryKind::Dir,
+            })
+            .collect();
+    }
+    if opts.only_dirs_with_files && opts.types == [EntryKind::Dir] {
+        eprintln!("xtree: --only-dirs-with-files needs files to match; add --type f");
+        process::exit(EXIT_ERROR);
+    }
+    if (opts.min_lines.is_some() || opts.max_lines.is_some()) && !opts.types.contains(&EntryKind::File) {
+        eprintln!("xtree: --min-lines and --max-lines match files; add --type f");
+        process::exit(EXIT_ERROR);
+    }
+    if matches.is_present("normalize") {
+        opts.set_normalize(true);
+    } else if matches.is_present("no-normalize") {
+        opts.set_normalize(false);
+    }
+    opts.set_ignore_accents(matches.is_present("ignore-accents"));
+    // Like tree(1), the full listing does not count the root among the directories.
+    opts.count_root = !matches.is_present("no-count-root") && !list_all;
+    opts.perms = matches.is_present("perms");
+    opts.show_empty = matches.is_present("show-empty");
+    opts.xtreeignore = !matches.is_present("no-xtreeignore");
+    opts.include = matches.values_of("include").map_or_else(Vec::new, |names| names.map(String::from).collect());
+    if let Some(names) = matches.value_of("smart-ignore") {
+        let given = names.trim_start_matches('+').split(',').filter(|name| !name.is_empty()).map(String::from);
+        opts.smart_ignore = if names.is_empty() || names.starts_with('+') {
+            SMART_IGNORE_DIRS.iter().map(|name| name.to_string()).chain(given).collect()
+        } else {
+            given.collect()
+        };
+    }
+    opts.cache = matches.is_present("cache");
+    opts.min_score = matches.value_of("min-score").map_or(1, |min| number_arg("min-score", min, false));
+    if matches.is_present("same-filesystem") {
+        opts.root_dev = opts.files.target_metadata(Path::new(directory)).ok().and_then(|metadata| metadata.dev);
+        if opts.root_dev.is_none() && cfg!(not(unix)) {
+            eprintln!("xtree: --same-filesystem is not supported on this platform; ignoring");
+        }
+    }
+    if let Some(rev) = matches.value_of("since-commit") {
+        match changed_since(directory, rev) {
+            Ok(changed) => opts.changed = Some(changed),
+            Err(err) => {
+                eprintln!("xtree: --since-commit: {}", err);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    let template = match matches.value_of("template").map(Template::parse) {
+        Some(Ok(template)) => Some(template),
+        Some(Err(err)) => {
+            eprintln!("xtree: invalid --template: {}", err);
+            process::exit(EXIT_ERROR);
+        }
+        None => None,
+    };
+    let background = matches.value_of("highlight-bg").map(|name| match parse_background(name) {
+        Some(code) => code,
+        None => {
+            eprintln!("xtree: invalid --highlight-bg color {} (use e.g. blue, bright-yellow or 44)", name);
+            process::exit(EXIT_ERROR);
+        }
+    });
+    if let Some(placeholder) = matches.value_of("summary-format").and_then(unknown_summary_placeholder) {
+        eprintln!("xtree: unknown --summary-format placeholder {} (use {{count}} or {{one|many}})", placeholder);
+        process::exit(EXIT_ERROR);
+    }
+    let count = match matches.value_of("count") {
+        Some("dirs") => Count::Dirs,
+        Some("files") => Count::Files,
+        _ => Count::All,
+    };
+    let color =
+        ColorChoice::from_flags(matches.value_of("color").unwrap_or("auto"), matches.is_present("no-color")).resolve();
+    let mut popts = PrintOptions {
+        terms_lower: &opts.terms_lower,
+        ls_colors: env::var("LS_COLORS")
+            .ok()
+            .filter(|_| color && opts.types != [EntryKind::Dir])
+            .map(|spec| LsColors::parse(&spec)),
+        format: if matches.is_present("socket") { "ndjson" } else { matches.value_of("format").unwrap_or("tree") },
+        json_pretty: matches.is_present("json-pretty"),
+        breadth_first: matches.is_present("breadth-first"),
+        legend: matches.is_present("legend"),
+        replace: replace_from.as_deref().zip(replace.as_ref().map(|replace| replace[1])),
+        histogram: matches.is_present("histogram"),
+        tips: matches.is_present("tips"),
+        rank: matches.is_present("rank"),
+        grep: matches.is_present("grep"),
+        breadcrumbs: matches.is_present("breadcrumbs").then(|| {
+            let default = if matches.is_present("ascii") { ">" } else { "›" };
+            matches.value_of("breadcrumb-separator").unwrap_or(default)
+        }),
+        recent: matches.value_of("recent").map(|limit| number_arg("recent", limit, true)),
+        color,
+        indent_only: matches.is_present("indent-only"),
+        ascii: matches.is_present("ascii"),
+        rtl: matches.is_present("rtl"),
+        depth_colors: matches.value_of("depth-colors").filter(|_| color),
+        summary: !matches.is_present("no-summary"),
+        summary_format: matches.value_of("summary-format").unwrap_or(match count {
+            Count::Dirs => DEFAULT_SUMMARY_FORMAT,
+            Count::Files => FILE_SUMMARY_FORMAT,
+            Count::All if opts.types == [EntryKind::Dir] => DEFAULT_SUMMARY_FORMAT,
+            Count::All => MIXED_SUMMARY_FORMAT,
+        }),
+        count,
+        template,
+        show_empty: opts.show_empty,
+        normalize: opts.normalize(),
+        highlight: HighlightStyle {
+            ignore_accents: opts.ignore_accents(),
+            case_sensitive: opts.case_sensitive() && !matches.is_present("highlight-ignore-case"),
+            background,
+            max_distance: opts.max_distance,
+        },
+        quiet_errors: matches.is_present("quiet-errors"),
+        report_depth: matches.is_present("report-depth"),
+        stats_json: matches.value_of("stats-json"),
+        relative_to: matches
+            .value_of("relative-to")
+            .map(|base| std::path::absolute(base).unwrap_or_else(|_| PathBuf::from(base))),
+        socket: matches.value_of("socket"),
+        merge_roots: merged,
+        anonymize: matches.is_present("anonymize"),
+        sort: matches.value_of("sort"),
+        reverse: matches.is_present("reverse"),
+        // Links would give the hidden names of --anonymize away.
+        hyperlinks: matches.is_present("hyperlinks")
+            && color
+            && io::stdout().is_terminal()
+            && !matches.is_present("anonymize"),
+        display_depth: matches.value_of("display-depth").map(|depth| number_arg("display-depth", depth, false)),
+        flatten_depth: matches.value_of("flatten-depth").map(|depth| number_arg("flatten-depth", depth, false)),
+        summary_tree: matches.is_present("summary-tree"),
+        sample: matches.value_of("sample").map(|limit| number_arg("sample", limit, true)),
+        max_matches_per_dir: matches.value_of("max-matches-per-dir").map(|max| number_arg("max-matches-per-dir", max, true)),
+        max_width: matches.value_of("max-width").map(|width| number_arg("max-width", width, true)),
+    };
+
+    if matches.is_present("print-config") {
+        if let Err(err) = print_config(directory, &opts, &popts) {
+            eprintln!("xtree: cannot write configuration: {}", err);
+            process::exit(EXIT_ERROR);
+        }
+        return;
+    }
+
+    if opts.max_depth >= LARGE_SCAN_DEPTH && !matches.is_present("yes") {
+        let roots = diff.iter().flatten().chain(&popts.merge_roots).copied().chain([directory]);
+        let mut roots = roots.filter(|&dir| opts.archive_format(Path::new(dir)).is_none());
+        if let Some(root) = roots.find(|&dir| is_filesystem_root(dir)) {
+            confirm_large_scan(root, opts.max_depth);
+        }
+    }
+
+    let exec = matches.value_of("exec").map(|command| (command, false));
+    if let Some((command, batch)) = exec.or(matches.value_of("exec-batch").map(|command| (command, true))) {
+        let command = split_command(command).unwrap_or_else(|err| {
+            eprintln!("xtree: invalid --{}: {}", if batch { "exec-batch" } else { "exec" }, err);
+            process::exit(EXIT_ERROR);
+        });
+        if !exec_matches(directory, &opts, &command, batch) {
+            process::exit(EXIT_NO_MATCH);
+        }
+    } else if list_extensions {
+        if !report_extensions(directory, &opts, &popts) {
+            process::exit(EXIT_NO_MATCH);
+        }
+    } else if let Some(expected) = &expected {
+        if !report_missing(directory, &opts, expected) {
+            process::exit(EXIT_NO_MATCH);
+        }
+    } else if matches.is_present("dedupe-names") {
+        if !report_duplicates(directory, &opts, &popts) {
+            process::exit(EXIT_NO_MATCH);
+        }
+    } else if let Some(dirs) = &diff {
+        if !render_diff(dirs[0], dirs[1], &opts, &popts) {
+            process::exit(EXIT_NO_MATCH);
+        }
+    } else if matches.is_present("expand") {
+        if !expand(directory, &opts, &mut popts) {
+            process::exit(EXIT_NO_MATCH);
+        }
+    } else if matches.is_present("watch") {
+        if let Err(err) = watch(directory, &opts, &popts) {
+            eprintln!("xtree: --watch: {}", err);
+            process::exit(EXIT_ERROR);
+        }
+    } else if !render(directory, &opts, &popts, search_matches.is_some_and(|matches| matches.is_present("apply"))) {
+        process::exit(EXIT_NO_MATCH);
+    }
+}
+
+/// Returns true if `dir` is the root of a filesystem: `/`, or a drive root such as `C:\`.
+fn is_filesystem_root(dir: &str) -> bool {
+    fs::canonicalize(dir).is_ok_and(|path| path.parent().is_none())
+}
+
+/// Asks on the terminal whether to go on with a scan of the filesystem root `root` down to
+/// `depth` levels, and exits unless the answer is yes. Without a terminal to ask on, refuses.
+fn confirm_large_scan(root: &str, depth: usize) {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        eprintln!("xtree: scanning {} down to depth {} can take very long; pass --yes to scan it anyway", root, depth);
+        process::exit(EXIT_ERROR);
+    }
+    eprint!("xtree: scan {} down to depth {}? This can take very long [y/N] ", root, depth);
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        process::exit(EXIT_ERROR);
+    }
+}
+
+/// Draws the tree of `directory` one level at a time (`--expand`): first the matches at depth 1,
+/// then one level deeper each time Enter is pressed, until the deepest level scanned (or
+/// `--display-depth`) is shown or `q` is entered. Unless both stdin and stdout are terminals,
+/// prints the tree once at its final depth. Returns whether anything matched.
+fn expand(directory: &str, opts: &ScanOptions, popts: &mut PrintOptions) -> bool {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return render(directory, opts, popts, false);
+    }
+    // Scanned once: every level is drawn from the same tree, and the notes on stderr come once.
+    let (tree, report) = scan_tree(directory, opts, popts);
+    let last = popts.display_depth.map_or(report.deepest, |max| max.min(report.deepest)).max(1);
+    for level in 1..=last {
+        if level > 1 {
+            if popts.color {
+                print!("\x1b[2J\x1b[H");
+            } else {
+                println!();
+            }
+        }
+        popts.display_depth = Some(level);
+        if !print_tree_formats(tree.as_ref(), popts, false) {
+            return false;
+        }
+        if level == last {
+            break;
+        }
+        print!("-- depth {} of {}: Enter for more, q to stop -- ", level, last);
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).map_or(true, |read| read == 0) || answer.trim() == "q" {
+            break;
+        }
+    }
+    true
+}
+
+/// Scans `directory` and prints the result in the requested format; with `apply`, performs the
+/// renames previewed by `--replace` afterwards. Returns whether anything matched.
+fn render(directory: &str, opts: &ScanOptions, popts: &PrintOptions, apply: bool) -> bool {
+    let lines = popts.grep || popts.breadcrumbs.is_some();
+    let flat = popts.rank || popts.recent.is_some() || lines;
+    let streamed = matches!(popts.format, "ndjson" | "matches-json");
+    if (flat || streamed) && (popts.report_depth || popts.stats_json.is_some()) {
+        eprintln!("xtree: --report-depth and --stats-json need the tree, --format json or --format yaml");
+        process::exit(EXIT_ERROR);
+    }
+    if (flat || streamed) && (opts.scan_budget.is_some() || opts.only_dirs_with_files) {
+        eprintln!("xtree: --scan-budget and --only-dirs-with-files need the tree, --format json, yaml, org or html");
+        process::exit(EXIT_ERROR);
+    }
+    if streamed && (!popts.merge_roots.is_empty() || popts.anonymize || popts.sort.is_some() || popts.reverse) {
+        eprintln!("xtree: --merge-roots, --anonymize, --sort and --reverse need the tree, --format json or --format yaml");
+        process::exit(EXIT_ERROR);
+    }
+    if (flat || streamed) && opts.archive_format(Path::new(directory)).is_some() {
+        eprintln!("xtree: --rank, --recent, --grep, --breadcrumbs, --format ndjson and matches-json cannot read archives; use --format json or yaml");
+        process::exit(EXIT_ERROR);
+    }
+    if flat {
+        let found = match popts.recent {
+            Some(limit) => print_recent(directory, opts, popts, limit),
+            None if lines && !popts.rank => print_grep(directory, opts, popts),
+            None => print_ranked(directory, opts, popts),
+        };
+        // Like grep, --grep (and --breadcrumbs) prints nothing at all when nothing matches.
+        if !found && !lines {
+            println!("No directories match the search term.");
+        }
+        return found;
+    }
+    if let Some(addr) = popts.socket {
+        let mut socket = connect_socket(addr).unwrap_or_else(|err| {
+            eprintln!("xtree: cannot connect to socket {}: {}", addr, err);
+            process::exit(EXIT_ERROR);
+        });
+        // Unlike a closed stdout, a listener going away mid-scan is worth knowing about.
+        return match print_ndjson(&mut socket, directory, opts, popts.relative_to.as_deref()) {
+            Ok(found) => found,
+            Err(err) => {
+                eprintln!("xtree: cannot write to socket {}: {}", addr, err);
+                process::exit(EXIT_ERROR);
+            }
+        };
+    }
+    if popts.format == "matches-json" {
+        return match print_match_spans(directory, opts, popts) {
+            Ok(found) => found,
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => true,
+            Err(err) => {
+                eprintln!("xtree: cannot write JSON: {}", err);
+                process::exit(EXIT_ERROR);
+            }
+        };
+    }
+    if popts.format == "ndjson" {
+        return match print_ndjson(&mut io::stdout().lock(), directory, opts, popts.relative_to.as_deref()) {
+            Ok(found) => found,
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => true,
+            Err(err) => {
+                eprintln!("xtree: cannot write JSON: {}", err);
+                process::exit(EXIT_ERROR);
+            }
+        };
+    }
+    let (tree, _) = scan_tree(directory, opts, popts);
+    print_tree_formats(tree.as_ref(), popts, apply)
+}
+
+/// Scans `directory` (or the `--merge-roots`) into a tree, reporting on stderr what the scan
+/// skipped or cut short, and applies `--anonymize`, `--sort` and `--reverse` to it.
+fn scan_tree(directory: &str, opts: &ScanOptions, popts: &PrintOptions) -> (Option<Tree>, ScanReport) {
+    let started = Instant::now();
+    let mut report = ScanReport::default();
+    let mut tree = if popts.merge_roots.is_empty() {
+        build_tree_dict_with_report(directory, opts, &mut report).unwrap_or_else(|err| fail_scan(err))
+    } else {
+        merge_roots(&popts.merge_roots, opts, &mut report)
+    };
+    debug!("scanned {} in {:?}", directory, started.elapsed());
+    let skipped = &report.skipped;
+    if skipped.iter().any(|dir| dir == Path::new(directory)) {
+        // Only an archive root gets this far unreadable; a directory was checked up front.
+        eprintln!("xtree: cannot read archive {}; --log-level warn shows why", directory);
+        process::exit(EXIT_ERROR);
+    }
+    if !skipped.is_empty() && !popts.quiet_errors {
+        let noun = if skipped.len() == 1 { "directory" } else { "directories" };
+        eprintln!("xtree: {} {} skipped (unreadable); --log-level warn lists them", skipped.len(), noun);
+    }
+    if report.over_budget {
+        eprintln!("xtree: stopped after scanning {} directories (--scan-budget); the results are partial", report.scanned);
+    }
+    if popts.report_depth {
+        let limit = if report.capped { " (limit)" } else { "" };
+        eprintln!("xtree: deepest level scanned: {}{}", report.deepest, limit);
+    }
+    if let Some(file) = popts.stats_json {
+        if let Err(err) = write_stats(file, tree.as_ref(), &report, started.elapsed()) {
+            eprintln!("xtree: cannot write stats to {}: {}", file, err);
+            process::exit(EXIT_ERROR);
+        }
+    }
+    if let Some(tree) = tree.as_mut().filter(|_| popts.anonymize) {
+        anonymize(tree);
+    }
+    if let (Some(tree), Some(order)) = (tree.as_mut(), popts.sort) {
+        tree.sort_by_mtime(order == "mtime-desc");
+    }
+    if let Some(tree) = tree.as_mut().filter(|_| popts.reverse) {
+        tree.reverse();
+    }
+    (tree, report)
+}
+
+/// Prints a scanned `tree` in the requested format, as [`render`] does. Returns whether anything
+/// matched.
+fn print_tree_formats(tree: Option<&Tree>, popts: &PrintOptions, apply: bool) -> bool {
+    if popts.format == "json" {
+        if let Err(err) = print_json(tree, popts.json_pretty) {
+            eprintln!("xtree: cannot write JSON: {}", err);
+            process::exit(EXIT_ERROR);
+        }
+        return tree.is_some();
+    }
+    if popts.format == "org" {
+        let Some(tree) = tree else {
+            return false;
+        };
+        let mut out = io::stdout().lock();
+        return match print_org(&mut out, tree, 0).and_then(|_| out.flush()) {
+            Ok(()) => true,
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => true,
+            Err(err) => {
+                eprintln!("xtree: cannot write the outline: {}", err);
+                process::exit(EXIT_ERROR);
+            }
+        };
+    }
+    if popts.format == "html" {
+        let Some(tree) = tree else {
+            return false;
+        };
+        let mut out = BufWriter::new(io::stdout().lock());
+        return match print_html(&mut out, tree, popts).and_then(|_| out.flush()) {
+            Ok(()) => true,
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => true,
+            Err(err) => {
+                eprintln!("xtree: cannot write the page: {}", err);
+                process::exit(EXIT_ERROR);
+            }
+        };
+    }
+    if popts.format == "yaml" {
+        if let Err(err) = print_yaml(tree) {
+            eprintln!("xtree: cannot write YAML: {}", err);
+            process::exit(EXIT_ERROR);
+        }
+        return tree.is_some();
+    }
+
+    if let Some(tree) = tree {
+        if popts.tips {
+            print_tips(tree, popts);
+            return true;
+        }
+        // Collapse a copy only: the histogram, label counts and renames need every match.
+        let flattened = (popts.flatten_depth.is_some() || popts.summary_tree || popts.sample.is_some()).then(|| {
+            let mut flattened = tree.clone();
+            if let Some(depth) = popts.flatten_depth {
+                flattened.flatten(depth);
+            }
+            if let Some(limit) = popts.sample {
+                sample(&mut flattened, 1, limit, &mut Vec::new());
+            }
+            if popts.summary_tree {
+                flattened.summarize();
+            }
+            flattened
+        });
+        let shown = flattened.as_ref().unwrap_or(tree);
+        // Print the root directory, highlighted if it matched.
+        let header = if tree.matched && popts.color {
+            highlight_root(&tree.name, popts)
+        } else {
+            tree.name.clone()
+        };
+        let root = Path::new(&tree.name);
+        let line = Line {
+            name: hyperlink(header, root, popts),
+            node: shown,
+            path: root,
+            depth: 0,
+            prefix: "",
+            branch: "",
+        };
+        let header = fit_width(format_line(line, popts), popts.max_width);
+        // Print the rest of the tree.
+        if popts.breadth_first {
+            println!("{}", header);
+            print_tree_breadth_first(shown, popts);
+        } else {
+            let mut out = LineFlusher::new(io::stdout().lock());
+            let written = if popts.rtl {
+                // Lines are right-aligned to the widest, so the whole tree is drawn before any of it.
+                let mut lines = Vec::new();
+                print_tree(&mut lines, shown, popts, "", false, 1, root, None).and_then(|_| {
+                    let lines = String::from_utf8_lossy(&lines);
+                    out.write_all(align_right(std::iter::once(header.as_str()).chain(lines.lines())).as_bytes())
+                })
+            } else {
+                writeln!(out, "{}", header).and_then(|_| print_tree(&mut out, shown, popts, "", false, 1, root, None))
+            };
+            match written.and_then(|_| out.flush()) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return true,
+                Err(err) => {
+                    eprintln!("xtree: cannot write the tree: {}", err);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        // Counted on the full tree, so matches hidden by --display-depth and the like still count.
+        if popts.summary {
+            println!("\n{}", format_summary(popts.summary_format, popts.count.tally(tree)));
+        }
+        print_label_summary(tree);
+        if popts.histogram {
+            print_histogram(tree, popts);
+        }
+        if popts.legend {
+            print_legend(popts);
+        }
+        if let Some(replace) = popts.replace.filter(|_| apply) {
+            match apply_renames(tree, replace) {
+                Ok(renamed) => println!("Renamed {} {}", renamed, if renamed == 1 { "entry" } else { "entries" }),
+                Err(err) => {
+                    eprintln!("xtree: --apply: {}", err);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        true
+    } else {
+        println!("No directories match the search term.");
+        false
+    }
+}
+
+/// Renders once, then again after every burst of filesystem changes below `directory`, clearing
+/// the screen in between. Runs until interrupted.
+fn watch(directory: &str, opts: &ScanOptions, popts: &PrintOptions) -> notify::Result<()> {
+    // Events that arrive within this long of each other are handled as one change.
+    const DEBOUNCE: Duration = Duration::from_millis(200);
+
+    let (events, changes) = mpsc::channel();
+    // Like the scan, do not follow symlinks out of the tree.
+    let config = notify::Config::default().with_follow_symlinks(false);
+    let mut watcher = RecommendedWatcher::new(events, config)?;
+    watcher.watch(Path::new(directory), RecursiveMode::Recursive)?;
+    loop {
+        if popts.color {
+            print!("\x1b[2J\x1b[H");
+        } else {
+            println!();
+        }
+        let _ = render(directory, opts, popts, false);
+        let _ = io::stdout().flush();
+        if !wait_for_change(&changes, DEBOUNCE)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Blocks until `changes` reports something other than an access, then swallows whatever else
+/// arrives within `debounce` of the last event. Returns false once the watcher is gone.
+fn wait_for_change(changes: &mpsc::Receiver<notify::Result<notify::Event>>, debounce: Duration) -> notify::Result<bool> {
+    // Reading directories to render them produces access events; only changes count.
+    loop {
+        match changes.recv() {
+            Ok(event) => {
+                if !event?.kind.is_access() {
+                    break;
+                }
+            }
+            // The watcher is gone; nothing more will change.
+            Err(_) => return Ok(false),
+        }
+    }
+    while changes.recv_timeout(debounce).is_ok() {}
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_flusher_passes_on_every_batch_of_lines() {
+        let mut out = LineFlusher::new(Vec::new());
+        for line in 1..FLUSH_LINES {
+            writeln!(out, "line {}", line).expect("write line");
+        }
+        assert!(out.inner.get_ref().is_empty());
+        writeln!(out, "line {}", FLUSH_LINES).expect("write line");
+        let passed = out.inner.get_ref().len();
+        assert_eq!(out.inner.get_ref().iter().filter(|&&byte| byte == b'\n').count(), FLUSH_LINES);
+        // The next batch waits for its own lines.
+        writeln!(out, "line {}", FLUSH_LINES + 1).expect("write line");
+        assert_eq!(out.inner.get_ref().len(), passed);
+    }
+
+    #[test]
+    fn watch_ignores_access_events() {
+        use notify::event::{AccessKind, EventKind, ModifyKind};
+
+        let (events, changes) = mpsc::channel();
+        events.send(Ok(notify::Event::new(EventKind::Access(AccessKind::Read)))).expect("send event");
+        drop(events);
+        assert!(!wait_for_change(&changes, Duration::ZERO).expect("wait"));
+
+        let (events, changes) = mpsc::channel();
+        events.send(Ok(notify::Event::new(EventKind::Access(AccessKind::Read)))).expect("send event");
+        events.send(Ok(notify::Event::new(EventKind::Modify(ModifyKind::Any)))).expect("send event");
+        assert!(wait_for_change(&changes, Duration::ZERO).expect("wait"));
+    }
+
+    #[test]
+    fn watch_debounces_a_burst_of_changes_into_one() {
+        use notify::event::{CreateKind, EventKind};
+
+        let (events, changes) = mpsc::channel();
+        for _ in 0..3 {
+            events.send(Ok(notify::Event::new(EventKind::Create(CreateKind::File)))).expect("send event");
+        }
+        // `events` stays alive, so the debounce ends on its timeout rather than a disconnect.
+        assert!(wait_for_change(&changes, Duration::from_millis(50)).expect("wait"));
+        assert!(changes.try_recv().is_err(), "the burst was not swallowed");
+        drop(events);
+    }
+
+    #[test]
+    fn watch_passes_watcher_errors_on() {
+        let (events, changes) = mpsc::channel();
+        events.send(Err(notify::Error::generic("lost the watch"))).expect("send event");
+        assert!(wait_for_change(&changes, Duration::ZERO).is_err());
+    }
+}
\ No newline at end of file
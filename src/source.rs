@@ -0,0 +1,293 @@
+//! Where scans read directories from: the real filesystem, or a tree held in memory.
+//!
+//! Every listing, entry kind and modification time the scans and matchers need goes through
+//! [`ScanOptions::files`](crate::ScanOptions::files), and so do permissions, owners, device ids,
+//! extended attributes, `.xtreeignore` files and file contents (for `--min-lines`); only the
+//! contents of archives and the `--cache` still come from the real filesystem. [`OsFiles`] reads
+//! them with `std::fs`; [`MemoryFiles`] serves a synthetic tree, so matching can be exercised
+//! without touching disk.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::{extended_path, EntryKind};
+
+/// What a scan needs to know about one entry, symlinks not followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMetadata {
+    pub kind: EntryKind,
+    /// Size in bytes; directories count as empty.
+    pub len: u64,
+    /// Last modification time, when known.
+    pub modified: Option<SystemTime>,
+    /// Permission bits (e.g. `0o755`), when known.
+    pub mode: Option<u32>,
+    /// Id of the device holding the entry, where the platform exposes one.
+    pub dev: Option<u64>,
+    /// Owning user id, where the platform exposes one.
+    pub uid: Option<u32>,
+    /// Owning group id, where the platform exposes one.
+    pub gid: Option<u32>,
+}
+
+impl EntryMetadata {
+    /// Metadata of an entry of `kind` and `len` bytes, with nothing else known.
+    pub fn new(kind: EntryKind, len: u64) -> Self {
+        EntryMetadata {
+            kind,
+            len,
+            modified: None,
+            mode: None,
+            dev: None,
+            uid: None,
+            gid: None,
+        }
+    }
+}
+
+/// A filesystem the scans can read. Paths are the ones the scan builds: the root as given,
+/// joined with entry names.
+pub trait FileSource: fmt::Debug + Send + Sync {
+    /// Lists the directory at `path` with the kind of each entry, in no particular order.
+    /// Entries of no [`EntryKind`] (sockets, FIFOs, devices) are left out.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, EntryKind)>>;
+
+    /// Returns the metadata of the entry at `path`.
+    fn metadata(&self, path: &Path) -> io::Result<EntryMetadata>;
+
+    /// Returns the metadata of the entry at `path`, following symlinks; what the scan root
+    /// reports. Sources that do not record symlink targets answer [`FileSource::metadata`].
+    fn target_metadata(&self, path: &Path) -> io::Result<EntryMetadata> {
+        self.metadata(path)
+    }
+
+    /// Opens the file at `path` for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+
+    /// Reads the file at `path` as UTF-8 text.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let mut text = String::new();
+        self.open(path)?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    /// Returns the value of the extended attribute `name` of the entry at `path` (not following
+    /// symlinks), or `None` if it is unset. Fails with [`io::ErrorKind::Unsupported`] where the
+    /// platform has no extended attributes.
+    fn xattr(&self, path: &Path, name: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Returns the kind of the entry at `path`.
+    fn file_type(&self, path: &Path) -> io::Result<EntryKind> {
+        self.metadata(path).map(|metadata| metadata.kind)
+    }
+}
+
+/// The real filesystem, through `std::fs`; what [`ScanOptions::new`](crate::ScanOptions::new)
+/// uses. On Windows, paths are read in their extended-length form so directories nested beyond
+/// `MAX_PATH` still work.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsFiles;
+
+impl FileSource for OsFiles {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, EntryKind)>> {
+        Ok(fs::read_dir(extended_path(path))?
+            .flatten()
+            .filter_map(|entry| {
+                let kind = entry.file_type().ok().and_then(EntryKind::of)?;
+                Some((entry.file_name(), kind))
+            })
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<EntryMetadata> {
+        entry_metadata(&fs::symlink_metadata(extended_path(path))?)
+    }
+
+    fn target_metadata(&self, path: &Path) -> io::Result<EntryMetadata> {
+        entry_metadata(&fs::metadata(extended_path(path))?)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(fs::File::open(extended_path(path))?))
+    }
+
+    #[cfg(unix)]
+    fn xattr(&self, path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        if !xattr::SUPPORTED_PLATFORM {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        xattr::get(path, name)
+    }
+
+    #[cfg(not(unix))]
+    fn xattr(&self, _path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(unix)]
+fn entry_metadata(metadata: &fs::Metadata) -> io::Result<EntryMetadata> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(EntryMetadata {
+        modified: metadata.modified().ok(),
+        mode: Some(metadata.mode() & 0o7777),
+        dev: Some(metadata.dev()),
+        uid: Some(metadata.uid()),
+        gid: Some(metadata.gid()),
+        ..EntryMetadata::new(entry_kind(metadata)?, metadata.len())
+    })
+}
+
+/// Without Unix mode bits, the read-only flag stands in for the write bits.
+#[cfg(not(unix))]
+fn entry_metadata(metadata: &fs::Metadata) -> io::Result<EntryMetadata> {
+    Ok(EntryMetadata {
+        modified: metadata.modified().ok(),
+        mode: Some(if metadata.permissions().readonly() { 0o444 } else { 0o666 }),
+        ..EntryMetadata::new(entry_kind(metadata)?, metadata.len())
+    })
+}
+
+fn entry_kind(metadata: &fs::Metadata) -> io::Result<EntryKind> {
+    EntryKind::of(metadata.file_type()).ok_or_else(|| io::Error::other("unsupported entry type"))
+}
+
+/// A tree of entries held in memory, for tests and for consumers matching synthetic trees.
+///
+/// ```
+/// use std::path::Path;
+/// use std::sync::Arc;
+/// use xtree::{build_tree_dict, MemoryFiles, ScanOptions};
+///
+/// let mut files = MemoryFiles::new();
+/// files.add_dir("root/src/test").add_file("root/docs/test.md", 12);
+/// let mut opts = ScanOptions::new(["test"]);
+/// opts.files = Arc::new(files);
+/// let tree = build_tree_dict("root", &opts).expect("matches");
+/// assert_eq!(tree.count_matches(), 1);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFiles {
+    entries: BTreeMap<PathBuf, EntryMetadata>,
+    contents: BTreeMap<PathBuf, Vec<u8>>,
+    xattrs: BTreeMap<(PathBuf, String), Vec<u8>>,
+}
+
+impl MemoryFiles {
+    /// Creates an empty tree.
+    pub fn new() -> Self {
+        MemoryFiles::default()
+    }
+
+    /// Adds a directory at `path`, along with any missing parents.
+    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.insert(path.as_ref(), EntryKind::Dir, 0, None)
+    }
+
+    /// Adds a file of `len` bytes at `path`, along with any missing parent directories. Its
+    /// contents read as empty.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, len: u64) -> &mut Self {
+        self.insert(path.as_ref(), EntryKind::File, len, None)
+    }
+
+    /// Adds a symlink at `path`, along with any missing parent directories. Its target is not
+    /// recorded, as scans never follow symlinks.
+    pub fn add_symlink(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.insert(path.as_ref(), EntryKind::Symlink, 0, None)
+    }
+
+    /// Adds a file holding `contents` at `path`, along with any missing parent directories.
+    pub fn add_file_with_contents(&mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> &mut Self {
+        let contents = contents.into();
+        self.insert(path.as_ref(), EntryKind::File, contents.len() as u64, None);
+        self.contents.insert(path.as_ref().to_path_buf(), contents);
+        self
+    }
+
+    /// Sets the modification time of the entry at `path`, if there is one.
+    pub fn set_modified(&mut self, path: impl AsRef<Path>, modified: SystemTime) -> &mut Self {
+        self.update(path.as_ref(), |entry| entry.modified = Some(modified))
+    }
+
+    /// Sets the permission bits of the entry at `path`, if there is one.
+    pub fn set_mode(&mut self, path: impl AsRef<Path>, mode: u32) -> &mut Self {
+        self.update(path.as_ref(), |entry| entry.mode = Some(mode))
+    }
+
+    /// Sets the owning user and group ids of the entry at `path`, if there is one.
+    pub fn set_owner(&mut self, path: impl AsRef<Path>, uid: u32, gid: u32) -> &mut Self {
+        self.update(path.as_ref(), |entry| {
+            entry.uid = Some(uid);
+            entry.gid = Some(gid);
+        })
+    }
+
+    /// Sets the device id of the entry at `path`, if there is one.
+    pub fn set_device(&mut self, path: impl AsRef<Path>, dev: u64) -> &mut Self {
+        self.update(path.as_ref(), |entry| entry.dev = Some(dev))
+    }
+
+    /// Sets the extended attribute `name` of the entry at `path` to `value`, if there is one.
+    pub fn set_xattr(&mut self, path: impl AsRef<Path>, name: &str, value: impl Into<Vec<u8>>) -> &mut Self {
+        let path = path.as_ref();
+        if self.entries.contains_key(path) {
+            self.xattrs.insert((path.to_path_buf(), name.to_string()), value.into());
+        }
+        self
+    }
+
+    fn update(&mut self, path: &Path, change: impl FnOnce(&mut EntryMetadata)) -> &mut Self {
+        if let Some(entry) = self.entries.get_mut(path) {
+            change(entry);
+        }
+        self
+    }
+
+    fn insert(&mut self, path: &Path, kind: EntryKind, len: u64, modified: Option<SystemTime>) -> &mut Self {
+        for parent in path.ancestors().skip(1).filter(|parent| !parent.as_os_str().is_empty()) {
+            self.entries.entry(parent.to_path_buf()).or_insert(EntryMetadata::new(EntryKind::Dir, 0));
+        }
+        self.entries.insert(path.to_path_buf(), EntryMetadata { modified, ..EntryMetadata::new(kind, len) });
+        self
+    }
+}
+
+impl FileSource for MemoryFiles {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, EntryKind)>> {
+        if self.metadata(path)?.kind != EntryKind::Dir {
+            return Err(io::Error::other(format!("{} is not a directory", path.display())));
+        }
+        Ok(self
+            .entries
+            .iter()
+            .filter(|(entry, _)| entry.parent() == Some(path))
+            .filter_map(|(entry, metadata)| Some((entry.file_name()?.to_os_string(), metadata.kind)))
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<EntryMetadata> {
+        self.entries
+            .get(path)
+            .copied()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path.display())))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        if self.metadata(path)?.kind != EntryKind::File {
+            return Err(io::Error::other(format!("{} is not a file", path.display())));
+        }
+        Ok(Box::new(self.contents.get(path).map_or(&[][..], Vec::as_slice)))
+    }
+
+    fn xattr(&self, path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.metadata(path)?;
+        Ok(self.xattrs.get(&(path.to_path_buf(), name.to_string())).cloned())
+    }
+}
+
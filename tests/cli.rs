@@ -0,0 +1,1307 @@
+use std::fs;
+use std::path::Path;
+
+use assert_cmd::Command;
+use tempfile::TempDir;
+
+/// Creates a temporary `tree` directory containing every `/`-separated path in `dirs`.
+/// A fixed root name keeps the random temp name from matching search terms.
+fn layout(dirs: &[&str]) -> TempDir {
+    let tmp = tempfile::tempdir().expect("create temp dir");
+    fs::create_dir(tmp.path().join("tree")).expect("create root");
+    for dir in dirs {
+        fs::create_dir_all(tmp.path().join("tree").join(dir)).expect("create layout");
+    }
+    tmp
+}
+
+/// Runs xtree with `args` followed by the layout's root, returning its exit status and stdout.
+fn run(tmp: &TempDir, args: &[&str]) -> (bool, String) {
+    let root = tmp.path().join("tree");
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(args)
+        .arg(root)
+        .env_remove("NO_COLOR")
+        .output()
+        .expect("run xtree");
+    (output.status.success(), String::from_utf8(output.stdout).expect("utf-8 output"))
+}
+
+#[test]
+fn renders_nested_matches_with_footer() {
+    let root = layout(&["alpha/src/src"]);
+    let (ok, stdout) = run(&root, &["src", "--color", "never"]);
+    assert!(ok);
+    let body: Vec<&str> = stdout.lines().skip(1).collect();
+    assert_eq!(
+        body,
+        [
+            "└── alpha",
+            "    └── src",
+            "        └── src",
+            "",
+            "2 directories",
+        ]
+    );
+}
+
+#[test]
+fn reports_when_nothing_matches() {
+    let root = layout(&["alpha/beta"]);
+    let (ok, stdout) = run(&root, &["missing"]);
+    assert!(!ok);
+    assert_eq!(stdout, "No directories match the search term.\n");
+}
+
+#[test]
+fn exit_status_distinguishes_no_match_from_errors() {
+    let root = layout(&["alpha"]);
+    let status = |args: &[&str], dir: &str| {
+        Command::cargo_bin("xtree")
+            .expect("binary built")
+            .args(args)
+            .arg(root.path().join(dir))
+            .output()
+            .expect("run xtree")
+            .status
+            .code()
+    };
+    assert_eq!(status(&["alpha"], "tree"), Some(0));
+    assert_eq!(status(&["missing"], "tree"), Some(1));
+    assert_eq!(status(&["missing", "--format", "json"], "tree"), Some(1));
+    assert_eq!(status(&["alpha"], "no-such-dir"), Some(2));
+}
+
+#[test]
+fn footer_counts_every_match() {
+    let root = layout(&["one-x", "two-x/x", "three"]);
+    let (_, stdout) = run(&root, &["x", "--color", "never"]);
+    assert!(stdout.ends_with("\n3 directories\n"));
+    assert!(!stdout.contains("three"));
+}
+
+#[test]
+fn highlights_only_when_color_is_enabled() {
+    let root = layout(&["MySrcDir"]);
+    let (_, stdout) = run(&root, &["src", "--color", "always"]);
+    assert!(stdout.contains("My\x1b[91mSrc\x1b[0mDir"));
+
+    let (_, stdout) = run(&root, &["src", "--color", "never"]);
+    assert!(!stdout.contains('\x1b'));
+    assert!(stdout.contains("MySrcDir"));
+}
+
+#[test]
+fn normalize_matches_decomposed_names() {
+    // "café" stored decomposed (NFD), searched composed (NFC).
+    let root = layout(&["cafe\u{301}-menu"]);
+    let (_, stdout) = run(&root, &["caf\u{e9}", "--no-normalize"]);
+    assert_eq!(stdout, "No directories match the search term.\n");
+
+    let (_, stdout) = run(&root, &["caf\u{e9}", "--normalize", "--color", "always"]);
+    assert!(stdout.contains("\x1b[91mcaf\u{e9}\x1b[0m-menu"));
+    assert!(stdout.ends_with("\n1 directory\n"));
+}
+
+#[test]
+fn json_output_is_compact_unless_pretty() {
+    let root = layout(&["alpha/src"]);
+    let (_, stdout) = run(&root, &["src", "--format", "json"]);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.starts_with("{\"matches\":1,\"tree\":{\"id\":\"cbf29ce484222325\",\"name\":"));
+    // The id hashes the path below the root, `alpha/src`.
+    assert!(stdout.contains("{\"id\":\"be7d3689b927172a\",\"name\":\"src\",\"kind\":\"dir\",\"matched\":true,\"score\":1,\"children\":[]}"));
+
+    let (_, stdout) = run(&root, &["src", "--format", "json", "--json-pretty"]);
+    assert!(stdout.lines().count() > 1);
+    assert!(stdout.contains("\n  \"matches\": 1,"));
+
+    let (_, stdout) = run(&root, &["missing", "--format", "json"]);
+    assert_eq!(stdout, "{\"matches\":0,\"tree\":null}\n");
+}
+
+#[test]
+fn tips_lists_only_the_deepest_match_of_each_branch() {
+    let root = layout(&["x1/x2", "x3", "other/x4/plain"]);
+    let (_, stdout) = run(&root, &["x", "--tips", "--color", "never"]);
+    let tips: Vec<String> = stdout.lines().map(|line| line.replace('\\', "/")).collect();
+    assert_eq!(tips.len(), 3);
+    assert!(tips.iter().any(|tip| tip.ends_with("/tree/x1/x2")));
+    assert!(tips.iter().any(|tip| tip.ends_with("/tree/x3")));
+    assert!(tips.iter().any(|tip| tip.ends_with("/tree/other/x4")));
+}
+
+#[test]
+fn ndjson_prints_one_object_per_match() {
+    let root = layout(&["alpha/src", "src"]);
+    let (_, stdout) = run(&root, &["src", "--format", "ndjson"]);
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("valid JSON line"))
+        .collect();
+    assert_eq!(lines.len(), 2);
+    let mut depths: Vec<u64> = lines.iter().map(|line| line["depth"].as_u64().unwrap()).collect();
+    depths.sort();
+    assert_eq!(depths, [1, 2]);
+    assert!(lines.iter().all(|line| line["score"] == 1 && line["path"].is_string()));
+}
+
+#[test]
+fn max_width_truncates_without_counting_colors() {
+    let root = layout(&["a-very-long-src-directory-name"]);
+    let (_, plain) = run(&root, &["src", "--max-width", "12", "--color", "never"]);
+    assert!(plain.contains("\n└── a-very-…\n"));
+
+    let (_, colored) = run(&root, &["src", "--max-width", "12", "--color", "always"]);
+    assert!(colored.contains("\n└── a-very-…\n"));
+    let (_, colored) = run(&root, &["src", "--max-width", "18", "--color", "always"]);
+    assert!(colored.contains("\n└── a-very-long-\x1b[91ms…\x1b[0m\n"));
+
+    for width in ["nope", "0"] {
+        let (ok, stdout) = run(&root, &["src", "--max-width", width]);
+        assert!(!ok && stdout.is_empty(), "{}", width);
+    }
+}
+
+#[test]
+fn since_commit_keeps_only_changed_directories() {
+    let root = layout(&["old-src", "new-src"]);
+    fs::write(root.path().join("tree/old-src/a.txt"), "").expect("write file");
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=xtree", "-c", "user.email=xtree@example.com"])
+            .args(args)
+            .current_dir(root.path().join("tree"))
+            .output()
+            .expect("run git")
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "initial"]);
+    fs::write(root.path().join("tree/new-src/b.txt"), "").expect("write file");
+    git(&["add", "."]);
+
+    let (ok, stdout) = run(&root, &["src", "--since-commit", "HEAD", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("new-src") && !stdout.contains("old-src"));
+
+    let outside = layout(&["src"]);
+    let (ok, _) = run(&outside, &["src", "--since-commit", "HEAD"]);
+    assert!(!ok);
+}
+
+#[test]
+fn dash_terms_are_literal_after_double_dash_or_fixed() {
+    let root = layout(&["-weird-dir-", "plain"]);
+    let (ok, stdout) = run(&root, &["--color", "never", "--", "-weird-dir-"]);
+    assert!(ok);
+    assert!(stdout.contains("└── -weird-dir-\n"));
+    assert!(stdout.ends_with("\n1 directory\n"));
+
+    let (ok, stdout) = run(&root, &["--color", "never", "--fixed", "-weird"]);
+    assert!(ok);
+    assert!(stdout.contains("└── -weird-dir-\n"));
+}
+
+#[test]
+fn cache_picks_up_new_directories() {
+    let root = layout(&["one-src"]);
+    let cache = tempfile::tempdir().expect("create cache dir");
+    let search = || {
+        let output = Command::cargo_bin("xtree")
+            .expect("binary built")
+            .args(["src", "--cache", "--color", "never"])
+            .arg(root.path().join("tree"))
+            .env("XDG_CACHE_HOME", cache.path())
+            .env("LOCALAPPDATA", cache.path())
+            .output()
+            .expect("run xtree");
+        String::from_utf8(output.stdout).expect("utf-8 output")
+    };
+    assert!(search().ends_with("\n1 directory\n"));
+    assert_eq!(fs::read_dir(cache.path().join("xtree")).expect("cache written").count(), 1);
+
+    fs::create_dir(root.path().join("tree/two-src")).expect("create dir");
+    assert!(search().ends_with("\n2 directories\n"));
+}
+
+#[test]
+fn histogram_counts_matches_per_depth() {
+    let root = layout(&["x1/x2", "x3", "other/x4"]);
+    let (_, stdout) = run(&root, &["x", "--histogram", "--color", "never"]);
+    let bars: Vec<&str> = stdout.lines().filter(|line| line.starts_with('d')).collect();
+    assert_eq!(bars.len(), 2);
+    assert!(bars[0].starts_with("d1: #") && bars[0].ends_with("# 2"));
+    assert!(bars[1].starts_with("d2: #") && bars[1].ends_with("# 2"));
+}
+
+#[test]
+fn display_depth_hides_but_counts_deeper_matches() {
+    let root = layout(&["x1/x2/x3"]);
+    let (_, stdout) = run(&root, &["x", "--display-depth", "1", "--color", "never"]);
+    assert!(stdout.contains("└── x1\n"));
+    assert!(!stdout.contains("x2"));
+    assert!(stdout.ends_with("\n3 directories\n"));
+
+    let (ok, stdout) = run(&root, &["x", "--display-depth", "deep"]);
+    assert!(!ok && stdout.is_empty());
+}
+
+#[test]
+fn replace_previews_and_apply_renames_bottom_up() {
+    let root = layout(&["old-a/Old-b", "keep"]);
+    let (_, stdout) = run(&root, &["--replace", "old", "new", "--color", "never"]);
+    assert!(stdout.contains("└── old-a → new-a\n    └── Old-b → new-b\n"));
+    assert!(root.path().join("tree/old-a/Old-b").is_dir());
+
+    let (ok, stdout) = run(&root, &["--replace", "old", "new", "--apply", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.ends_with("Renamed 2 entries\n"));
+    assert!(root.path().join("tree/new-a/new-b").is_dir());
+
+    fs::create_dir(root.path().join("tree/old-a")).expect("create dir");
+    let (ok, _) = run(&root, &["--replace", "old", "new", "--apply"]);
+    assert!(!ok);
+    assert!(root.path().join("tree/old-a").is_dir());
+
+    // Outputs other than the tree would skip the renames, so they are refused.
+    for args in [&["--format", "json"][..], &["--grep"], &["--tips"]] {
+        let (ok, stdout) = run(&root, &[&["--replace", "old", "new", "--apply"][..], args].concat());
+        assert!(!ok && stdout.is_empty(), "{:?}", args);
+    }
+    assert!(root.path().join("tree/old-a").is_dir());
+}
+
+#[test]
+fn log_level_writes_to_stderr_only() {
+    let root = layout(&["src"]);
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["src", "--log-level", "trace", "--color", "never"])
+        .arg(root.path().join("tree"))
+        .env_remove("RUST_LOG")
+        .output()
+        .expect("run xtree");
+    let stderr = String::from_utf8(output.stderr).expect("utf-8 output");
+    assert!(stderr.contains("matches at depth 1"));
+    assert!(String::from_utf8(output.stdout).expect("utf-8 output").ends_with("\n1 directory\n"));
+}
+
+#[test]
+fn manifest_labels_matches_and_tallies_them() {
+    let root = layout(&["app/node_modules", "lib/vendor", "other"]);
+    let manifest = root.path().join("manifest.txt");
+    fs::write(&manifest, "# known dirs\nnode_modules=JS dep\nvendor = vendored\n").expect("write manifest");
+    let (ok, stdout) = run(&root, &["--match-manifest", manifest.to_str().unwrap(), "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("└── node_modules [JS dep]\n"));
+    assert!(stdout.contains("└── vendor [vendored]\n"));
+    assert!(stdout.ends_with("\n2 directories\nJS dep: 1, vendored: 1\n"));
+}
+
+#[test]
+fn ls_colors_tint_files_by_extension() {
+    let root = layout(&["src"]);
+    fs::write(root.path().join("tree/src/main.rs"), "").expect("write file");
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["main", "--type", "f", "--color", "always"])
+        .arg(root.path().join("tree"))
+        .env_remove("NO_COLOR")
+        .env("LS_COLORS", "di=01;34:*.rs=33")
+        .output()
+        .expect("run xtree");
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 output");
+    assert!(stdout.contains("── src\n"));
+    assert!(stdout.contains("\x1b[33m\x1b[91mmain\x1b[0m\x1b[33m.rs\x1b[0m"));
+}
+
+#[test]
+fn template_replaces_the_line_layout() {
+    let root = layout(&["a/src/src"]);
+    let (ok, stdout) = run(&root, &["src", "--color", "never", "--template", "{depth}:{prefix}{branch}{name} ({score})"]);
+    assert!(ok);
+    let body: Vec<&str> = stdout.lines().skip(1).take(3).collect();
+    assert_eq!(body, ["1:└── a (2)", "2:    └── src (2)", "3:        └── src (1)"]);
+
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["src", "--template", "{size}"])
+        .arg(root.path().join("tree"))
+        .output()
+        .expect("run xtree");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown placeholder `{size}`"));
+}
+
+#[test]
+fn list_options_prints_long_flags_but_stays_out_of_help() {
+    let output = Command::cargo_bin("xtree").expect("binary built").arg("--list-options").output().expect("run xtree");
+    assert!(output.status.success());
+    let options: Vec<String> = String::from_utf8(output.stdout).expect("utf-8 output").lines().map(str::to_owned).collect();
+    for flag in ["--help", "--depth", "--traverse-depth", "--term", "--format", "--template"] {
+        assert!(options.iter().any(|option| option == flag), "{} missing", flag);
+    }
+
+    let help = Command::cargo_bin("xtree").expect("binary built").arg("--help").output().expect("run xtree");
+    assert!(!String::from_utf8_lossy(&help.stdout).contains("list-options"));
+}
+
+#[test]
+fn min_children_matches_fan_out_directories() {
+    let root = layout(&["wide/a", "wide/b", "wide/c", "narrow/a"]);
+    let (ok, stdout) = run(&root, &["--min-children", "3", "--no-count-root", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("└── wide [3 subdirs]\n"));
+    assert!(!stdout.contains("narrow"));
+    assert!(stdout.ends_with("\n1 directory\n"));
+}
+
+#[test]
+fn diff_marks_entries_present_on_one_side() {
+    let tmp = layout(&["a/src/old", "a/src/lib", "b/src/lib", "b/src/new"]);
+    let root = tmp.path().join("tree");
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["-e", "lib", "-e", "old", "-e", "new", "--color", "never", "--diff"])
+        .args([root.join("a"), root.join("b")])
+        .output()
+        .expect("run xtree");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 output");
+    assert!(stdout.contains("    ├── - old\n") || stdout.contains("    └── - old\n"));
+    assert!(stdout.contains("    ├── + new\n") || stdout.contains("    └── + new\n"));
+    assert!(stdout.ends_with("\n1 in both, 1 removed, 1 added\n"));
+}
+
+#[test]
+fn flatten_depth_collapses_deeper_matches_into_counts() {
+    let root = layout(&["src/a/src", "src/src"]);
+    let (ok, stdout) = run(&root, &["src", "--flatten-depth", "1", "--color", "never"]);
+    assert!(ok);
+    let body: Vec<&str> = stdout.lines().skip(1).collect();
+    assert_eq!(body, ["└── src (+2 matches below)", "", "3 directories"]);
+
+    let (ok, stdout) = run(&root, &["src", "--flatten-depth", "one"]);
+    assert!(!ok && stdout.is_empty());
+}
+
+#[test]
+fn root_header_highlights_its_own_match() {
+    let root = layout(&["subtree"]);
+    // A bare `tree` would be the subcommand.
+    let (_, stdout) = run(&root, &["search", "tree", "--color", "always"]);
+    let header = stdout.lines().next().expect("header line");
+    assert!(header.ends_with("/\x1b[91mtree\x1b[0m"), "{:?}", header);
+    assert!(!header.trim_end_matches("/\x1b[91mtree\x1b[0m").contains('\x1b'));
+
+    let (_, stdout) = run(&root, &["search", "tree", "--color", "always", "--no-count-root"]);
+    assert!(!stdout.lines().next().expect("header line").contains('\x1b'));
+}
+
+#[test]
+fn scans_tarball_entries_as_a_tree() {
+    let tmp = tempfile::tempdir().expect("create temp dir");
+    let archive = tmp.path().join("build.tar");
+    let mut builder = tar::Builder::new(fs::File::create(&archive).expect("create archive"));
+    for path in ["app/config/", "app/lib/config/"] {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        builder.append_data(&mut header, path, std::io::empty()).expect("add entry");
+    }
+    builder.finish().expect("write archive");
+
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["config", "--color", "never"])
+        .arg(&archive)
+        .output()
+        .expect("run xtree");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 output");
+    assert!(stdout.contains("└── app\n    ├── config\n    └── lib\n        └── config\n"), "{}", stdout);
+    assert!(stdout.ends_with("\n2 directories\n"));
+}
+
+#[test]
+fn no_summary_drops_the_footer() {
+    let root = layout(&["src"]);
+    let (ok, stdout) = run(&root, &["src", "--no-summary", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.ends_with("\n└── src\n"), "{:?}", stdout);
+}
+
+#[test]
+fn size_bounds_match_on_total_subtree_size() {
+    let root = layout(&["big/nested", "small"]);
+    fs::write(root.path().join("tree/big/nested/blob"), vec![0u8; 3000]).expect("write file");
+    fs::write(root.path().join("tree/small/note"), "hi").expect("write file");
+
+    let (ok, stdout) = run(&root, &["--larger-than", "2KB", "--no-count-root", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("└── big [2.9 KiB]\n    └── nested [2.9 KiB]\n"), "{}", stdout);
+    assert!(!stdout.contains("small"));
+
+    let (_, stdout) = run(&root, &["small", "--smaller-than", "1K", "--color", "never"]);
+    assert!(stdout.contains("└── small [2 B]\n"));
+}
+
+#[test]
+fn relative_to_rebases_printed_paths() {
+    let root = layout(&["a/src"]);
+    let base = root.path().join("tree/a");
+    let (_, stdout) = run(&root, &["src", "--tips", "--color", "never", "--relative-to", base.to_str().expect("utf-8 path")]);
+    assert_eq!(stdout, "src\n");
+
+    let (_, stdout) = run(&root, &["src", "--grep", "--color", "never", "--relative-to", base.to_str().expect("utf-8 path")]);
+    assert_eq!(stdout, "src\n");
+    let (_, stdout) = run(&root, &["src", "--rank", "--no-summary", "--color", "never", "--relative-to", base.to_str().expect("utf-8 path")]);
+    assert_eq!(stdout, "src [src]\n");
+
+    let outside = tempfile::tempdir().expect("create temp dir");
+    let (_, stdout) = run(&root, &["src", "--tips", "--color", "never", "--relative-to", outside.path().to_str().expect("utf-8 path")]);
+    assert!(Path::new(stdout.trim_end()).is_absolute());
+}
+
+#[test]
+fn rank_lists_matches_by_relevance() {
+    let root = layout(&["test", "fixtures", "api/test-fixtures-test", "other/test-test"]);
+    let (ok, stdout) = run(&root, &["--rank", "-e", "test", "-e", "fixtures", "--no-summary", "--color", "never"]);
+    assert!(ok);
+    let names: Vec<&str> = stdout.lines().map(|line| line.rsplit('/').next().expect("path")).collect();
+    assert_eq!(names, ["test-fixtures-test [test ×2, fixtures]", "test-test [test ×2]", "fixtures [fixtures]", "test [test]"]);
+}
+
+#[test]
+fn never_color_emits_no_escape_sequences() {
+    let root = layout(&["src/lib", "docs/src", "empty"]);
+    fs::write(root.path().join("tree/src/main.rs"), "").expect("write file");
+    let renderers: [&[&str]; 8] = [
+        &["src", "--legend", "--histogram", "--show-empty", "--hyperlinks", "--perms"],
+        &["src", "-t", "f,d", "--indent-only"],
+        &["src", "--breadth-first"],
+        &["src", "--tips"],
+        &["src", "--rank", "-e", "lib"],
+        &["src", "--format", "json"],
+        &["src", "--format", "ndjson"],
+        &["src", "--template", "{prefix}{branch}{name} {score}"],
+    ];
+    for args in renderers {
+        for color in [["--color", "never"], ["--no-color", "--color=always"]] {
+            let output = Command::cargo_bin("xtree")
+                .expect("binary built")
+                .args(args)
+                .args(color)
+                .arg(root.path().join("tree"))
+                .env("LS_COLORS", "*.rs=01;31:fi=33")
+                .env("CI", "1")
+                .env("TERM", "xterm")
+                .output()
+                .expect("run xtree");
+            assert!(output.status.success(), "{:?}", args);
+            assert!(!output.stdout.contains(&0x1b), "{:?} {:?} printed an escape", args, color);
+        }
+    }
+}
+
+#[test]
+fn recent_lists_the_newest_matches_first() {
+    let root = layout(&["old-src", "mid-src", "new-src", "src-too"]);
+    let now = std::time::SystemTime::now();
+    for (dir, hours) in [("old-src", 50), ("mid-src", 5), ("new-src", 1), ("src-too", 100)] {
+        let dir = fs::File::open(root.path().join("tree").join(dir)).expect("open dir");
+        dir.set_modified(now - std::time::Duration::from_secs(hours * 60 * 60)).expect("set mtime");
+    }
+    let (ok, stdout) = run(&root, &["src", "--recent", "2", "--no-summary", "--color", "never"]);
+    assert!(ok);
+    let names: Vec<&str> = stdout.lines().map(|line| line.rsplit('/').next().expect("path")).collect();
+    assert_eq!(names, ["new-src [1h ago]", "mid-src [5h ago]"]);
+
+    for limit in ["abc", "0"] {
+        let (ok, stdout) = run(&root, &["src", "--recent", limit]);
+        assert!(!ok && stdout.is_empty(), "{}", limit);
+    }
+}
+
+#[test]
+fn expect_lists_names_missing_from_the_tree() {
+    let root = layout(&["src/tests", "docs"]);
+    let expected = root.path().join("expected.txt");
+    fs::write(&expected, "# scaffold\nsrc\nTests\nci\n").expect("write expected names");
+    let (ok, stdout) = run(&root, &["--expect", expected.to_str().unwrap()]);
+    assert!(!ok);
+    assert_eq!(stdout, "ci\n\n1 of 3 expected names missing\n");
+
+    fs::write(&expected, "docs\n").expect("write expected names");
+    let (ok, stdout) = run(&root, &["--expect", expected.to_str().unwrap()]);
+    assert!(ok);
+    assert_eq!(stdout, "All 1 expected name found.\n");
+}
+
+#[test]
+fn smart_case_turns_case_sensitive_on_uppercase_terms() {
+    let root = layout(&["MySrc", "src"]);
+    let (_, stdout) = run(&root, &["Src", "--smart-case", "--color", "never"]);
+    assert!(stdout.contains("── MySrc\n"));
+    assert!(stdout.ends_with("\n1 directory\n"));
+
+    let (_, stdout) = run(&root, &["src", "--smart-case", "--color", "never"]);
+    assert!(stdout.ends_with("\n2 directories\n"));
+
+    let (_, stdout) = run(&root, &["Src", "--smart-case", "-i", "--color", "never"]);
+    assert!(stdout.ends_with("\n2 directories\n"));
+}
+
+#[test]
+fn highlight_ignore_case_decouples_highlighting_from_matching() {
+    let root = layout(&["my-test-Test", "test"]);
+    let (_, stdout) = run(&root, &["Test", "-s", "--color", "always"]);
+    assert!(stdout.contains("my-test-\x1b[91mTest\x1b[0m"));
+    assert!(!stdout.contains("── test"));
+
+    let (_, stdout) = run(&root, &["Test", "-s", "--highlight-ignore-case", "--color", "always"]);
+    assert!(stdout.contains("my-\x1b[91mtest\x1b[0m-Test"));
+    assert!(!stdout.contains("── test"));
+}
+
+#[test]
+fn max_matches_per_dir_sums_up_the_rest() {
+    let root = layout(&["logs"]);
+    for i in 0..4 {
+        fs::write(root.path().join(format!("tree/logs/run{}.log", i)), "").expect("write file");
+    }
+    let (ok, stdout) = run(&root, &["run", "--type", "f", "--max-matches-per-dir", "1", "--color", "never"]);
+    assert!(ok);
+    assert_eq!(stdout.matches(".log\n").count(), 1);
+    assert!(stdout.contains("    └── ... (3 more matches)\n"));
+    assert!(stdout.ends_with("\n4 matches\n"));
+
+    for max in ["many", "0"] {
+        let (ok, stdout) = run(&root, &["run", "--type", "f", "--max-matches-per-dir", max]);
+        assert!(!ok && stdout.is_empty(), "{}", max);
+    }
+}
+
+#[test]
+fn report_depth_tells_whether_the_limit_was_hit() {
+    let root = layout(&["a/b"]);
+    let report = |depth: &str| {
+        let output = Command::cargo_bin("xtree")
+            .expect("binary built")
+            .args(["b", "--report-depth", "--depth", depth])
+            .arg(root.path().join("tree"))
+            .output()
+            .expect("run xtree");
+        String::from_utf8(output.stderr).expect("utf-8 output")
+    };
+    assert_eq!(report("1"), "xtree: deepest level scanned: 1 (limit)\n");
+    assert_eq!(report("5"), "xtree: deepest level scanned: 2\n");
+}
+
+#[test]
+fn subcommands_route_search_tree_and_completions() {
+    let root = layout(&["src/tests", "docs"]);
+    let (_, bare) = run(&root, &["src", "--color", "never"]);
+    let (_, search) = run(&root, &["search", "src", "--color", "never"]);
+    assert_eq!(bare, search);
+
+    let (ok, stdout) = run(&root, &["tree", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("── tests\n"));
+    assert!(stdout.ends_with("\n3 directories\n"));
+    let (ok, _) = run(&root, &["tree", "--term", "src"]);
+    assert!(!ok);
+
+    let output = Command::cargo_bin("xtree").expect("binary built").args(["completions", "bash"]).output().expect("run xtree");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("_xtree()"));
+}
+
+#[cfg(unix)]
+#[test]
+fn count_picks_what_the_footer_tallies() {
+    let root = layout(&["log/x"]);
+    fs::write(root.path().join("tree/log/log.txt"), "").expect("write file");
+    fs::write(root.path().join("tree/log/x/log2.txt"), "").expect("write file");
+    let footer = |count: &str| {
+        let (_, stdout) = run(&root, &["log", "--type", "f", "--type", "d", "--count", count, "--color", "never"]);
+        stdout.lines().last().expect("footer").to_string()
+    };
+    assert_eq!(footer("dirs"), "1 directory");
+    assert_eq!(footer("files"), "2 files");
+    assert_eq!(footer("all"), "3 matches");
+}
+
+#[test]
+fn stats_json_writes_scan_statistics_beside_the_tree() {
+    let root = layout(&["a/test", "b/test/deep", "c"]);
+    let stats = root.path().join("stats.json");
+    let (ok, stdout) = run(&root, &["test", "--stats-json", stats.to_str().unwrap(), "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.ends_with("\n2 directories\n"));
+    let stats: serde_json::Value = serde_json::from_slice(&fs::read(stats).expect("read stats")).expect("parse stats");
+    assert_eq!(stats["version"], 1);
+    assert_eq!(stats["matches"], 2);
+    assert_eq!(stats["scanned"], 6);
+    assert_eq!(stats["skipped"], 0);
+    assert_eq!(stats["max_depth"], 3);
+    assert_eq!(stats["depth_capped"], true);
+    assert_eq!(stats["matches_by_depth"], serde_json::json!([0, 0, 2]));
+}
+
+#[test]
+fn min_match_length_refuses_short_terms_unless_forced() {
+    let root = layout(&["e", "test"]);
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["e", "--min-match-length", "3"])
+        .arg(root.path().join("tree"))
+        .output()
+        .expect("run xtree");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("shorter than --min-match-length 3"));
+
+    let (ok, _) = run(&root, &["test", "--min-match-length", "3", "--color", "never"]);
+    assert!(ok);
+    let (ok, _) = run(&root, &["e", "--min-match-length", "3", "--force", "--color", "never"]);
+    assert!(ok);
+}
+
+#[test]
+fn yaml_format_nests_the_tree_and_quotes_awkward_names() {
+    let root = layout(&["a/test", "2024"]);
+    let (ok, stdout) = run(&root, &["test", "-e", "2024", "--format", "yaml"]);
+    assert!(ok);
+    assert!(stdout.starts_with("matches: 2\ntree:\n"));
+    assert!(stdout.contains("name: '2024'\n"));
+    assert!(!stdout.contains('\x1b'));
+    let doc: serde_json::Value = serde_yaml::from_str(&stdout).expect("valid YAML");
+    assert_eq!(doc["tree"]["children"].as_array().map(Vec::len), Some(2));
+    let (ok, stdout) = run(&root, &["nothing", "--format", "yaml"]);
+    assert!(!ok);
+    assert_eq!(stdout, "matches: 0\ntree: null\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn group_rejects_unknown_names() {
+    let root = layout(&["a/src"]);
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["src", "--group", "no-such-group-xtree"])
+        .arg(root.path().join("tree"))
+        .output()
+        .expect("run xtree");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn grep_lists_paths_with_the_match_highlighted() {
+    let root = layout(&["api/test-x", "docs"]);
+    let (ok, stdout) = run(&root, &["test", "--grep", "--color", "never"]);
+    assert!(ok);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.ends_with("/tree/api/test-x\n"));
+
+    let (_, stdout) = run(&root, &["test", "--grep", "--color", "always"]);
+    assert!(stdout.starts_with("\x1b[35m"));
+    assert!(stdout.ends_with("/api/\x1b[91mtest\x1b[0m\x1b[35m-x\x1b[0m\n"));
+
+    let (ok, stdout) = run(&root, &["nothing", "--grep"]);
+    assert!(!ok);
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn expand_prints_the_full_tree_without_a_terminal() {
+    let root = layout(&["a/test/b/test", "test"]);
+    let (ok, expanded) = run(&root, &["test", "--expand", "--color", "never"]);
+    assert!(ok);
+    let (_, plain) = run(&root, &["test", "--color", "never"]);
+    assert_eq!(expanded, plain);
+}
+
+#[test]
+fn socket_streams_ndjson_to_a_listener() {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let root = layout(&["a/test", "b"]);
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+    let addr = listener.local_addr().expect("listener address").to_string();
+    let reader = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept connection");
+        let mut received = String::new();
+        stream.read_to_string(&mut received).expect("read stream");
+        received
+    });
+    let (ok, stdout) = run(&root, &["test", "--socket", &addr]);
+    assert!(ok);
+    assert!(stdout.is_empty());
+    let received = reader.join().expect("reader thread");
+    assert_eq!(received.lines().count(), 1);
+    assert!(received.contains("\"depth\":2"));
+
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["test", "--socket"])
+        .arg(root.path().join("no-such.sock"))
+        .arg(root.path().join("tree"))
+        .output()
+        .expect("run xtree");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot connect to socket"));
+}
+
+#[test]
+fn merge_roots_overlays_common_paths() {
+    let root = layout(&["a/src/test", "b/src/test", "b/docs/test"]);
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["test", "--color", "never", "--merge-roots"])
+        .args([root.path().join("tree/a"), root.path().join("tree/b")])
+        .output()
+        .expect("run xtree");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 output");
+    assert_eq!(stdout.matches("── src").count(), 1);
+    assert!(stdout.contains("── test [in 2 roots]\n"));
+    assert!(stdout.ends_with("\n2 directories\n"));
+}
+
+#[test]
+fn anonymize_hides_names_that_did_not_match() {
+    let root = layout(&["secret/test", "private/x/test"]);
+    let (ok, stdout) = run(&root, &["test", "--anonymize", "--color", "never"]);
+    assert!(ok);
+    assert!(!stdout.contains("secret") && !stdout.contains("private") && !stdout.contains("tree"));
+    assert!(stdout.starts_with("dir_cbf29c\n"));
+    assert_eq!(stdout.matches("── test\n").count(), 2);
+    let (_, again) = run(&root, &["test", "--anonymize", "--color", "never"]);
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    let mut again: Vec<&str> = again.lines().collect();
+    lines.sort_unstable();
+    again.sort_unstable();
+    assert_eq!(lines, again);
+}
+
+#[test]
+fn max_distance_matches_typos() {
+    let root = layout(&["confguration", "configuration", "other"]);
+    let (_, stdout) = run(&root, &["configuration", "--color", "never"]);
+    assert!(stdout.ends_with("\n1 directory\n"));
+    let (_, stdout) = run(&root, &["configuration", "--max-distance", "1", "--rank", "--color", "never"]);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[0].ends_with("/configuration [configuration]"));
+    assert!(lines[1].ends_with("/confguration [configuration]"));
+    assert!(stdout.ends_with("\n2 directories\n"));
+}
+
+#[test]
+fn summary_tree_folds_single_directory_chains() {
+    let root = layout(&["a/b/c/test", "a/x-test/d/test", "e/test"]);
+    let (ok, stdout) = run(&root, &["test", "--summary-tree", "--depth", "6", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("── e/test\n"));
+    assert!(stdout.contains("── b/c/test\n"));
+    assert!(stdout.contains("── x-test\n"));
+    assert!(stdout.contains("── d/test\n"));
+    assert!(stdout.contains("── a\n"));
+    assert!(stdout.ends_with("\n4 directories\n"));
+}
+
+#[test]
+fn org_format_prints_an_outline() {
+    let root = layout(&["a/test"]);
+    let (ok, stdout) = run(&root, &["test", "--format", "org", "--color", "always"]);
+    assert!(ok);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[0].starts_with("* ") && lines[0].ends_with("/tree"));
+    assert_eq!(&lines[1..], ["** a", "*** =test="]);
+
+    let (ok, stdout) = run(&root, &["nothing", "--format", "org"]);
+    assert!(!ok);
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn dedupe_names_groups_matches_sharing_a_name() {
+    let root = layout(&["a/utils", "b/utils", "c/x/utils", "lib-utils"]);
+    let (ok, stdout) = run(&root, &["utils", "--dedupe-names", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.starts_with("utils (3)\n  "));
+    assert!(!stdout.contains("lib-utils"));
+    assert!(stdout.ends_with("\n1 name shared by 3 matches\n"));
+
+    let (ok, _) = run(&root, &["lib", "--dedupe-names"]);
+    assert!(!ok);
+}
+
+#[test]
+fn xtree_root_is_the_default_directory() {
+    let root = layout(&["src", "other/src-x"]);
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["src", "--color", "never"])
+        .env("XTREE_ROOT", root.path().join("tree/other"))
+        .output()
+        .expect("run xtree");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("── src-x\n"));
+    assert!(stdout.ends_with("\n1 directory\n"));
+
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["src", "--color", "never"])
+        .arg(root.path().join("tree"))
+        .env("XTREE_ROOT", root.path().join("tree/other"))
+        .output()
+        .expect("run xtree");
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with("\n2 directories\n"));
+}
+
+#[test]
+fn sort_mtime_orders_entries_by_age() {
+    let root = layout(&["logs"]);
+    let now = std::time::SystemTime::now();
+    for (name, hours) in [("b-run.log", 2), ("a-run.log", 1), ("c-run.log", 3)] {
+        let path = root.path().join("tree/logs").join(name);
+        let file = fs::File::create(&path).expect("write file");
+        file.set_modified(now - std::time::Duration::from_secs(hours * 3600)).expect("set mtime");
+    }
+    let order = |sort: &str| -> Vec<String> {
+        let (_, stdout) = run(&root, &["run", "--type", "f", "--sort", sort, "--color", "never"]);
+        stdout.lines().filter_map(|line| line.rsplit(' ').next().filter(|name| name.ends_with(".log"))).map(String::from).collect()
+    };
+    assert_eq!(order("mtime"), ["c-run.log", "b-run.log", "a-run.log"]);
+    assert_eq!(order("mtime-desc"), ["a-run.log", "b-run.log", "c-run.log"]);
+}
+
+#[test]
+fn breadcrumbs_chain_the_ancestors_of_each_match() {
+    let root = layout(&["src/module/test"]);
+    let (ok, stdout) = run(&root, &["test", "--breadcrumbs", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.ends_with("tree › src › module › test\n"));
+    assert_eq!(stdout.lines().count(), 1);
+
+    let (_, stdout) = run(&root, &["test", "--breadcrumbs", "--ascii", "--color", "never"]);
+    assert!(stdout.ends_with("tree > src > module > test\n"));
+    let (_, stdout) = run(&root, &["test", "--breadcrumbs", "--breadcrumb-separator", "/", "--color", "never"]);
+    assert!(stdout.ends_with("tree / src / module / test\n"));
+    let (_, stdout) = run(&root, &["test", "--breadcrumbs", "--color", "always"]);
+    assert!(stdout.ends_with(" \x1b[2m›\x1b[0m \x1b[91mtest\x1b[0m\n"));
+
+    let (_, stdout) = run(&root, &["test", "--ascii", "--color", "never"]);
+    assert!(stdout.contains("`-- src\n    `-- module\n        `-- test\n"));
+}
+
+#[test]
+fn depth_colors_count_levels_from_the_root_or_the_first_match() {
+    let root = layout(&["src/module/test"]);
+    let (ok, stdout) = run(&root, &["test", "--depth-colors", "--color", "always"]);
+    assert!(ok);
+    assert!(stdout.contains("\x1b[36m└── \x1b[0msrc\n"));
+    assert!(stdout.contains("\x1b[35m└── \x1b[0m\x1b[91mtest\x1b[0m\n"));
+
+    let (_, stdout) = run(&root, &["test", "--depth-colors=relative", "--color", "always"]);
+    assert!(stdout.contains("\x1b[2m└── \x1b[0msrc\n"));
+    assert!(stdout.contains("\x1b[36m└── \x1b[0m\x1b[91mtest\x1b[0m\n"));
+
+    let (_, stdout) = run(&root, &["test", "--depth-colors", "--color", "never"]);
+    assert!(!stdout.contains('\x1b'));
+}
+
+#[test]
+fn list_extensions_counts_files_by_extension() {
+    let root = layout(&["src/module"]);
+    let tree = root.path().join("tree");
+    for file in ["main.rs", "src/lib.rs", "src/module/mod.rs", "README.md", "LICENSE"] {
+        fs::write(tree.join(file), "").expect("write file");
+    }
+    let (ok, stdout) = run(&root, &["--list-extensions"]);
+    assert!(ok);
+    assert_eq!(stdout, "3  .rs\n1  (none)\n1  .md\n\n5 files, 3 extensions\n");
+
+    let (_, stdout) = run(&root, &["--list-extensions", "--sort", "name", "--no-summary"]);
+    assert_eq!(stdout, "1  (none)\n1  .md\n3  .rs\n");
+    let (ok, _) = run(&root, &["--list-extensions", "--sort", "mtime"]);
+    assert!(!ok);
+}
+
+#[cfg(unix)]
+#[test]
+fn exec_runs_a_command_per_match_or_once_for_all() {
+    let root = layout(&["a/test", "b/test"]);
+    let (ok, stdout) = run(&root, &["test", "--exec", "echo 'found:' {}"]);
+    assert!(ok);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines.iter().all(|line| line.starts_with("found: ") && line.ends_with("/test")));
+
+    let (ok, stdout) = run(&root, &["test", "--exec-batch", "echo {} end"]);
+    assert!(ok);
+    assert_eq!(stdout.lines().count(), 1);
+    assert_eq!(stdout.matches("/test").count(), 2);
+    assert!(stdout.ends_with(" end\n"));
+
+    // Failures are reported once every command ran.
+    let (ok, stdout) = run(&root, &["test", "--exec", "sh -c 'echo ran; exit 3'"]);
+    assert!(!ok);
+    assert_eq!(stdout, "ran\nran\n");
+}
+
+#[test]
+fn split_terms_turns_one_argument_into_several_terms() {
+    let root = layout(&["foo", "bar", "foo,bar"]);
+    let (ok, stdout) = run(&root, &["foo,bar", "--split-terms", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.ends_with("\n3 directories\n"));
+    let (_, stdout) = run(&root, &["foo;bar", "--split-terms=;", "--color", "never"]);
+    assert!(stdout.ends_with("\n3 directories\n"));
+
+    let (_, stdout) = run(&root, &["foo,bar", "--color", "never"]);
+    assert!(stdout.ends_with("\n1 directory\n"));
+}
+
+#[test]
+fn scan_budget_stops_after_listing_n_directories() {
+    let root = layout(&["a/x/y", "b/x/y", "c/x/y"]);
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["x", "--scan-budget", "2", "--color", "never"])
+        .arg(root.path().join("tree"))
+        .output()
+        .expect("run xtree");
+    assert!(output.status.success());
+    // The root and one top-level directory are listed, so only one `x` is found.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.ends_with("\n1 directory\n"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("stopped after scanning 2 directories"));
+
+    let (ok, stdout) = run(&root, &["x", "--scan-budget", "100", "--color", "never"]);
+    assert!(ok && stdout.ends_with("\n3 directories\n"));
+    let (ok, _) = run(&root, &["x", "--scan-budget", "2", "--grep"]);
+    assert!(!ok);
+}
+
+#[test]
+fn rtl_mirrors_the_tree() {
+    let root = layout(&["src/test"]);
+    let (ok, stdout) = run(&root, &["test", "--rtl", "--color", "never"]);
+    assert!(ok);
+    let lines: Vec<&str> = stdout.lines().take(3).collect();
+    // Lines end in the same column: the root's, the widest.
+    assert!(lines[0].starts_with('\u{2068}') && lines[0].ends_with("tree\u{2069}"));
+    assert!(lines[1].ends_with(" \u{2068}src\u{2069} ──┘"));
+    assert!(lines[2].ends_with(" \u{2068}test\u{2069} ──┘    "));
+    assert_eq!(lines[1].chars().count(), lines[2].chars().count());
+    assert!(stdout.ends_with("\n1 directory\n"));
+
+    let (_, stdout) = run(&root, &["test", "--rtl", "--ascii", "--color", "never"]);
+    assert!(stdout.contains("\u{2068}test\u{2069} --'    \n"));
+}
+
+#[test]
+fn only_dirs_with_files_match_directories_holding_matching_files() {
+    let root = layout(&["holder/deep", "test-named", "other"]);
+    let tree = root.path().join("tree");
+    fs::write(tree.join("holder/test.rs"), "").expect("write file");
+    fs::write(tree.join("other/notes"), "").expect("write file");
+    let (ok, stdout) = run(&root, &["test", "--type", "f,d", "--only-dirs-with-files", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("\n└── holder\n    └── test.rs\n"));
+    assert!(!stdout.contains("test-named"));
+    // The directory and its file.
+    assert!(stdout.ends_with("\n2 matches\n"));
+
+    let (ok, _) = run(&root, &["test", "--only-dirs-with-files"]);
+    assert!(!ok);
+}
+
+#[test]
+fn line_bounds_match_files_by_line_count() {
+    let root = layout(&["src"]);
+    let src = root.path().join("tree/src");
+    fs::write(src.join("long.rs"), "a\nb\nc").expect("write file");
+    fs::write(src.join("short.rs"), "a\n").expect("write file");
+    fs::write(src.join("empty.rs"), "").expect("write file");
+    fs::write(src.join("binary.rs"), b"a\0\nb\nc\n").expect("write file");
+    let (ok, stdout) = run(&root, &["rs", "--type", "f", "--min-lines", "2", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("└── long.rs [3 lines]\n"));
+    assert!(stdout.ends_with("\n1 match\n"));
+
+    let (_, stdout) = run(&root, &["--type", "f", "--max-lines", "0", "--color", "never"]);
+    assert!(stdout.contains("└── empty.rs [0 lines]\n"));
+    assert!(stdout.ends_with("\n1 match\n"));
+    let (ok, _) = run(&root, &["rs", "--min-lines", "2"]);
+    assert!(!ok);
+}
+
+#[test]
+fn reverse_flips_the_order_of_every_directory() {
+    let root = layout(&["logs"]);
+    let now = std::time::SystemTime::now();
+    for (name, hours) in [("b-run.log", 2), ("a-run.log", 1), ("c-run.log", 3)] {
+        let path = root.path().join("tree/logs").join(name);
+        let file = fs::File::create(&path).expect("write file");
+        file.set_modified(now - std::time::Duration::from_secs(hours * 3600)).expect("set mtime");
+    }
+    let order = |sort: &str| -> Vec<String> {
+        let (_, stdout) = run(&root, &["run", "--type", "f", "--sort", sort, "--reverse", "--color", "never"]);
+        stdout.lines().filter_map(|line| line.rsplit(' ').next().filter(|name| name.ends_with(".log"))).map(String::from).collect()
+    };
+    assert_eq!(order("mtime"), ["a-run.log", "b-run.log", "c-run.log"]);
+    assert_eq!(order("mtime-desc"), ["c-run.log", "b-run.log", "a-run.log"]);
+
+    let (ok, _) = run(&root, &["run", "--reverse", "--grep"]);
+    assert!(!ok);
+}
+
+#[test]
+fn smart_ignore_collapses_noise_directories() {
+    let root = layout(&["app/src", "app/node_modules/left-pad/src", "app/node_modules/lodash", "app/out/src"]);
+    let (ok, stdout) = run(&root, &["src", "-d", "5", "--smart-ignore", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains(" node_modules [ignored, 2 top-level entries]\n"));
+    assert!(!stdout.contains("left-pad"));
+    assert!(stdout.ends_with("\n2 directories\n"));
+
+    let (_, stdout) = run(&root, &["src", "--smart-ignore=+out", "--color", "never"]);
+    assert!(stdout.contains(" out [ignored, 1 top-level entry]\n"));
+    assert!(stdout.ends_with("\n1 directory\n"));
+    let (_, stdout) = run(&root, &["src", "-d", "5", "--smart-ignore", "--no-smart-ignore", "--color", "never"]);
+    assert!(stdout.contains("left-pad") && !stdout.contains("ignored"));
+}
+
+#[test]
+fn sample_keeps_the_first_matches_of_each_depth() {
+    let root = layout(&["a-log/x-log", "b-log/y-log", "c-log/z-log"]);
+    let (ok, stdout) = run(&root, &["log", "--sample", "1", "--color", "never"]);
+    assert!(ok);
+    // One match per level: a top-level directory and the one inside it.
+    let drawn: Vec<&str> = stdout.lines().skip(1).take_while(|line| !line.is_empty()).collect();
+    assert_eq!(drawn.len(), 2);
+    assert!(drawn[0].starts_with("└── ") && drawn[1].starts_with("    └── "));
+    assert!(stdout.ends_with("\n6 directories\n"));
+
+    let (ok, _) = run(&root, &["log", "--sample", "0"]);
+    assert!(!ok);
+}
+
+#[test]
+fn matches_json_reports_the_highlighted_spans() {
+    let root = layout(&["foo-bar", "plain-foo"]);
+    let (ok, stdout) = run(&root, &["foo", "-e", "bar", "--format", "matches-json"]);
+    assert!(ok);
+    let spans: serde_json::Value = serde_json::from_str(&stdout).expect("valid JSON");
+    let mut spans: Vec<(String, u64, u64, String)> = spans
+        .as_array()
+        .expect("an array")
+        .iter()
+        .map(|span| {
+            let name = span["path"].as_str().and_then(|path| path.rsplit('/').next()).expect("path");
+            let (start, end) = (span["match_start"].as_u64().expect("start"), span["match_end"].as_u64().expect("end"));
+            (name.to_string(), start, end, span["term"].as_str().expect("term").to_string())
+        })
+        .collect();
+    spans.sort();
+    assert_eq!(
+        spans,
+        [
+            ("foo-bar".to_string(), 0, 3, "foo".to_string()),
+            ("foo-bar".to_string(), 4, 7, "bar".to_string()),
+            ("plain-foo".to_string(), 6, 9, "foo".to_string()),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn deep_scans_of_the_filesystem_root_need_yes() {
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["no-such-name-anywhere", "/", "--depth", "8"])
+        .output()
+        .expect("run xtree");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pass --yes"));
+    assert!(output.stdout.is_empty());
+
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["no-such-name-anywhere", "/", "--depth", "1"])
+        .output()
+        .expect("run xtree");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn name_length_bounds_count_characters() {
+    let root = layout(&["short", "a-rather-long-name", "ééééééé"]);
+    let (ok, stdout) = run(&root, &["--name-longer-than", "6", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("── a-rather-long-name [18 chars]\n"));
+    assert!(stdout.contains("── ééééééé [7 chars]\n"));
+    assert!(stdout.ends_with("\n2 directories\n"));
+
+    let (_, stdout) = run(&root, &["long", "--name-shorter-than", "10", "--color", "never"]);
+    assert!(stdout.starts_with("No directories"));
+}
+
+#[test]
+fn html_format_is_a_standalone_collapsible_page() {
+    let root = layout(&["a&'test/x-test", "other"]);
+    let (ok, stdout) = run(&root, &["test", "--format", "html", "--highlight-bg", "blue"]);
+    assert!(ok);
+    assert!(stdout.starts_with("<!DOCTYPE html>\n"));
+    assert!(stdout.ends_with("</html>\n"));
+    assert!(stdout.contains("<li class=\"dir match\"><span class=\"name\">a&amp;&#39;<span class=\"hl t0\">test</span></span><ul>\n"));
+    assert!(stdout.contains(".hl { background-color: navy; }"));
+    assert!(stdout.contains("classList.toggle(\"collapsed\")"));
+    assert!(stdout.contains("<p class=\"summary\">2 directories</p>"));
+    assert!(!stdout.contains("other"));
+
+    let (ok, stdout) = run(&root, &["nothing", "--format", "html"]);
+    assert!(!ok);
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn legend_names_the_color_of_each_term() {
+    let root = layout(&["src-lib"]);
+    let (ok, stdout) = run(&root, &["src", "-e", "lib", "--legend", "--color", "always"]);
+    assert!(ok);
+    assert_eq!(stdout.lines().last(), Some("Legend: \x1b[91mred\x1b[0m = term 'src', \x1b[92mgreen\x1b[0m = term 'lib'"));
+
+    let (_, stdout) = run(&root, &["src", "--legend", "--color", "never"]);
+    assert!(!stdout.contains("Legend"));
+}
+
+#[test]
+fn breadth_first_prints_each_level_before_the_next() {
+    let root = layout(&["x1/x2/x3", "x4", "other/x5"]);
+    let (ok, stdout) = run(&root, &["x", "--breadth-first", "--color", "never"]);
+    assert!(ok);
+    let lines: Vec<String> = stdout.lines().skip(1).take_while(|line| !line.is_empty()).map(|line| line.replace('\\', "/")).collect();
+    let depth = |line: &String| (line.len() - line.trim_start().len()) / 2;
+    assert_eq!(lines.len(), 6);
+    assert!(lines.windows(2).all(|pair| depth(&pair[0]) <= depth(&pair[1])), "{:?}", lines);
+    assert_eq!(lines[5], "      x1/x2/x3");
+    assert!(lines[3..5].contains(&"    x1/x2".to_string()) && lines[3..5].contains(&"    other/x5".to_string()));
+}
+
+#[test]
+fn print_config_writes_json_to_stderr_without_scanning() {
+    let root = layout(&["src"]);
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["src", "--depth", "5", "--print-config"])
+        .arg(root.path().join("tree"))
+        .output()
+        .expect("run xtree");
+    assert!(output.status.success());
+    // A scan would have printed the match, or that nothing matched.
+    assert!(output.stdout.is_empty());
+    let config: serde_json::Value = serde_json::from_slice(&output.stderr).expect("JSON on stderr");
+    assert_eq!(config["scan"]["max_depth"], 5);
+    assert_eq!(config["scan"]["terms_lower"], serde_json::json!(["src"]));
+    assert!(config["directory"].as_str().is_some_and(|dir| dir.ends_with("tree")));
+}
+
+#[test]
+fn hyperlinks_are_left_out_when_stdout_is_not_a_terminal() {
+    let root = layout(&["a/src"]);
+    let (ok, stdout) = run(&root, &["src", "--hyperlinks", "--color", "always"]);
+    assert!(ok);
+    assert!(!stdout.contains("\x1b]8;"), "{:?}", stdout);
+    assert!(stdout.contains("\x1b[91msrc\x1b[0m"));
+}
+
+#[cfg(unix)]
+#[test]
+fn quiet_errors_drops_the_skipped_directories_line() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = layout(&["open/src", "locked/src"]);
+    let locked = root.path().join("tree/locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).expect("lock directory");
+    if fs::read_dir(&locked).is_ok() {
+        // Running as root, which reads the directory anyway.
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).expect("unlock directory");
+        return;
+    }
+    let stderr = |args: &[&str]| {
+        let output = Command::cargo_bin("xtree")
+            .expect("binary built")
+            .args(args)
+            .arg(root.path().join("tree"))
+            .output()
+            .expect("run xtree");
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).expect("utf-8 output")
+    };
+    let reported = stderr(&["src", "--color", "never"]);
+    let quiet = stderr(&["src", "--color", "never", "--quiet-errors"]);
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).expect("unlock directory");
+    assert!(reported.contains("xtree: 1 directory skipped (unreadable)"), "{:?}", reported);
+    assert!(!quiet.contains("skipped"), "{:?}", quiet);
+}
+
+#[test]
+fn min_score_keeps_only_branches_with_enough_matches() {
+    let root = layout(&["a/x1", "a/x2", "b/x3"]);
+    let (ok, stdout) = run(&root, &["x", "--min-score", "2", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("└── a\n"), "{}", stdout);
+    assert!(!stdout.contains("x3"));
+    assert!(stdout.ends_with("\n2 directories\n"));
+
+    let (ok, stdout) = run(&root, &["x", "--min-score", "xyz"]);
+    assert!(!ok && stdout.is_empty());
+}
+
+#[test]
+fn terms_file_skips_blank_lines_and_comments() {
+    let root = layout(&["api", "docs", "web", "#notes"]);
+    let terms = root.path().join("terms.txt");
+    fs::write(&terms, "#notes\napi\n\n  \nweb\n").expect("write terms");
+    let (ok, stdout) = run(&root, &["--terms-file", terms.to_str().unwrap(), "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.contains("── api\n") && stdout.contains("── web\n"));
+    assert!(!stdout.contains("docs") && !stdout.contains("notes"));
+    assert!(stdout.ends_with("\n2 directories\n"));
+
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["--terms-file", "no-such-terms.txt"])
+        .arg(root.path().join("tree"))
+        .output()
+        .expect("run xtree");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot read terms file no-such-terms.txt"));
+}
+
+#[test]
+fn summary_format_fills_in_the_count_and_the_word_for_it() {
+    let root = layout(&["one-x", "two-x"]);
+    let (ok, stdout) = run(&root, &["x", "--summary-format", "found {count} {match|matches}", "--color", "never"]);
+    assert!(ok);
+    assert!(stdout.ends_with("\nfound 2 matches\n"));
+    let (_, stdout) = run(&root, &["one", "--summary-format", "found {count} {match|matches}", "--color", "never"]);
+    assert!(stdout.ends_with("\nfound 1 match\n"));
+
+    let output = Command::cargo_bin("xtree")
+        .expect("binary built")
+        .args(["x", "--summary-format", "{count} {dirs}"])
+        .arg(root.path().join("tree"))
+        .output()
+        .expect("run xtree");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown --summary-format placeholder {dirs}"));
+}